@@ -0,0 +1,48 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { EscrowAnchor } from "../target/types/escrow_anchor";
+
+type DealDetails = anchor.IdlAccounts<EscrowAnchor>["dealDetails"];
+
+// A native SOL leg has no mint, its side of the pair is indexed under the default public key
+const mintSeed = (mint: anchor.web3.PublicKey | null) => (mint ?? anchor.web3.PublicKey.default).toBuffer();
+
+export const orderIndexPda = (
+  programId: anchor.web3.PublicKey,
+  mintA: anchor.web3.PublicKey | null,
+  mintB: anchor.web3.PublicKey | null,
+  page = 0
+) => {
+  const pageSeed = Buffer.alloc(4);
+  pageSeed.writeUInt32LE(page);
+  return anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("index"), mintSeed(mintA), mintSeed(mintB), pageSeed],
+    programId
+  )[0];
+};
+
+// Deals of a mint pair that can still be filled, read from every page of its order index instead of scanning every deal.
+// A deal past its expiry stays listed until someone expires it, so those are filtered out here
+export async function fetchOpenDeals(
+  program: Program<EscrowAnchor>,
+  mintA: anchor.web3.PublicKey | null,
+  mintB: anchor.web3.PublicKey | null
+): Promise<{ address: anchor.web3.PublicKey; deal: DealDetails }[]> {
+  // pages of the pair, matched on the mint_a and mint_b fields that follow the discriminator
+  const pages = await program.account.orderIndex.all([
+    { memcmp: { offset: 8, bytes: anchor.utils.bytes.bs58.encode(mintSeed(mintA)) } },
+    { memcmp: { offset: 40, bytes: anchor.utils.bytes.bs58.encode(mintSeed(mintB)) } },
+  ]);
+  const addresses = pages.flatMap((page) => page.account.deals);
+
+  const deals = await program.account.dealDetails.fetchMultiple(addresses);
+  const now = Math.floor(Date.now() / 1000);
+  const openDeals: { address: anchor.web3.PublicKey; deal: DealDetails }[] = [];
+  addresses.forEach((address, i) => {
+    const deal = deals[i];
+    if (deal && deal.expiresAt.toNumber() > now) {
+      openDeals.push({ address, deal });
+    }
+  });
+  return openDeals;
+}
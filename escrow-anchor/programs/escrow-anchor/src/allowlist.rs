@@ -0,0 +1,26 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::keccak::hashv;
+
+use crate::{ErrorCode, TakerAllowlist};
+
+// Fills of an allowlisted deal only go through for a listed taker, or one with a Merkle proof against the root
+pub(crate) fn require_allowed_taker(allowlist: &Option<TakerAllowlist>, taker: &Pubkey, proof: &[[u8; 32]]) -> Result<()> {
+    let allowed = match allowlist {
+        None => true,
+        Some(TakerAllowlist::Takers { takers }) => takers.contains(taker),
+        Some(TakerAllowlist::MerkleRoot { root }) => merkle_root(taker, proof) == *root,
+    };
+    require!(allowed, ErrorCode::TakerNotAllowed);
+    Ok(())
+}
+
+// pairs are hashed smaller first, so a proof is just the sibling hashes from the leaf up
+fn merkle_root(taker: &Pubkey, proof: &[[u8; 32]]) -> [u8; 32] {
+    proof.iter().fold(hashv(&[taker.as_ref()]).to_bytes(), |node, sibling| {
+        if node <= *sibling {
+            hashv(&[&node, sibling]).to_bytes()
+        } else {
+            hashv(&[sibling, &node]).to_bytes()
+        }
+    })
+}
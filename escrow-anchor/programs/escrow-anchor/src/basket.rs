@@ -0,0 +1,164 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_2022::spl_token_2022::{
+    extension::StateWithExtensions,
+    state::{Account as TokenAccountState, Mint as MintState},
+};
+use anchor_spl::token_2022::{close_account, CloseAccount};
+use anchor_spl::token_interface::TransferChecked;
+
+use crate::extensions::{gross_amount, transfer_checked_with_hook};
+use crate::{BasketDetails, BasketLeg, DealDetails, ErrorCode};
+
+// Helpers around the extra legs of a basket deal. Instructions that move basket funds take every leg's accounts
+// first in remaining_accounts, in leg order, followed by whatever transfer hook accounts the transfers need
+
+// escrow, mint, the user's token account for the leg and the mint's token program
+pub(crate) const LEG_ACCOUNTS: usize = 4;
+
+pub(crate) struct LegAccounts<'info> {
+    pub escrow_token_acc: AccountInfo<'info>,
+    pub mint: AccountInfo<'info>,
+    pub user_token_acc: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+}
+
+// Legs of the deal, empty for a plain deal. The basket account is checked against its PDA here
+// rather than through seeds so clients of plain deals can leave it out
+pub(crate) fn basket_legs<'a>(
+    deal_details: &Account<DealDetails>,
+    basket_details: &'a Option<Account<BasketDetails>>,
+) -> Result<&'a [BasketLeg]> {
+    match basket_details {
+        Some(basket_details) => {
+            let basket_key = Pubkey::create_program_address(
+                &[b"basket", deal_details.key().as_ref(), &[basket_details.basket_details_bump]],
+                &crate::ID,
+            )
+            .map_err(|_| ErrorCode::InvalidBasketAccount)?;
+            require_keys_eq!(basket_details.key(), basket_key, ErrorCode::InvalidBasketAccount);
+            Ok(&basket_details.legs)
+        }
+        None => {
+            require!(deal_details.basket_legs == 0, ErrorCode::MissingBasketAccounts);
+            Ok(&[])
+        }
+    }
+}
+
+// Transfer hook accounts, everything in remaining_accounts after the basket legs
+pub(crate) fn hook_accounts<'a, 'info>(remaining_accounts: &'a [AccountInfo<'info>], legs: &[BasketLeg]) -> &'a [AccountInfo<'info>] {
+    remaining_accounts.get(legs.len() * LEG_ACCOUNTS..).unwrap_or(&[])
+}
+
+// Picks the accounts of leg `index` out of remaining_accounts and checks the escrow and mint belong to it
+pub(crate) fn leg_accounts<'info>(
+    remaining_accounts: &[AccountInfo<'info>],
+    deal: &Pubkey,
+    index: usize,
+    leg: &BasketLeg,
+) -> Result<LegAccounts<'info>> {
+    let accounts = remaining_accounts
+        .get(index * LEG_ACCOUNTS..(index + 1) * LEG_ACCOUNTS)
+        .ok_or(ErrorCode::MissingBasketAccounts)?;
+
+    let escrow_key = Pubkey::create_program_address(
+        &[b"leg", deal.as_ref(), &[index as u8], &[leg.escrow_token_acc_bump]],
+        &crate::ID,
+    )
+    .map_err(|_| ErrorCode::InvalidBasketAccount)?;
+    require_keys_eq!(accounts[0].key(), escrow_key, ErrorCode::InvalidBasketAccount);
+    require_keys_eq!(accounts[1].key(), leg.mint, ErrorCode::InvalidMint);
+    require_keys_eq!(*accounts[1].owner, accounts[3].key(), ErrorCode::InvalidTokenProgram);
+
+    Ok(LegAccounts {
+        escrow_token_acc: accounts[0].clone(),
+        mint: accounts[1].clone(),
+        user_token_acc: accounts[2].clone(),
+        token_program: accounts[3].clone(),
+    })
+}
+
+// Taker side legs are paid in on deposit, the taker covers any transfer fee like on the main leg
+pub(crate) fn fund_leg<'info>(
+    leg_accounts: &LegAccounts<'info>,
+    leg: &BasketLeg,
+    authority: &AccountInfo<'info>,
+    hook_accounts: &[AccountInfo<'info>],
+) -> Result<()> {
+    let cpi_accounts = TransferChecked {
+        mint: leg_accounts.mint.clone(),
+        from: leg_accounts.user_token_acc.clone(),
+        to: leg_accounts.escrow_token_acc.clone(),
+        authority: authority.clone(),
+    };
+    let cpi_context = CpiContext::new(leg_accounts.token_program.clone(), cpi_accounts).with_remaining_accounts(hook_accounts.to_vec());
+    transfer_checked_with_hook(
+        cpi_context,
+        gross_amount(&leg_accounts.mint, leg.mint_amt)?,
+        mint_decimals(&leg_accounts.mint)?,
+    )
+}
+
+// Pays out everything a leg escrow holds to an account of `recipient` and closes the escrow, rent goes to the maker
+pub(crate) fn release_leg<'info>(
+    leg_accounts: &LegAccounts<'info>,
+    recipient: &Pubkey,
+    maker: &AccountInfo<'info>,
+    controller: &AccountInfo<'info>,
+    hook_accounts: &[AccountInfo<'info>],
+    signer_seeds: &[&[&[u8]]],
+) -> Result<u64> {
+    let (_, amount) = token_account_state(&leg_accounts.escrow_token_acc)?;
+    if amount > 0 {
+        let (owner, _) = token_account_state(&leg_accounts.user_token_acc)?;
+        require_keys_eq!(owner, *recipient, ErrorCode::InvalidUser);
+
+        let cpi_accounts = TransferChecked {
+            mint: leg_accounts.mint.clone(),
+            from: leg_accounts.escrow_token_acc.clone(),
+            to: leg_accounts.user_token_acc.clone(),
+            authority: controller.clone(),
+        };
+        let cpi_context = CpiContext::new(leg_accounts.token_program.clone(), cpi_accounts)
+            .with_signer(signer_seeds)
+            .with_remaining_accounts(hook_accounts.to_vec());
+        transfer_checked_with_hook(cpi_context, amount, mint_decimals(&leg_accounts.mint)?)?;
+    }
+
+    let cpi_accounts = CloseAccount {
+        account: leg_accounts.escrow_token_acc.clone(),
+        destination: maker.clone(),
+        authority: controller.clone(),
+    };
+    close_account(CpiContext::new_with_signer(leg_accounts.token_program.clone(), cpi_accounts, signer_seeds))?;
+    Ok(amount)
+}
+
+// Releases every leg of the basket to the maker, used when the deal is called off before it is filled
+pub(crate) fn refund_basket<'info>(
+    remaining_accounts: &[AccountInfo<'info>],
+    deal: &Pubkey,
+    legs: &[BasketLeg],
+    maker: &AccountInfo<'info>,
+    controller: &AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    let hook_accounts = hook_accounts(remaining_accounts, legs);
+    for (index, leg) in legs.iter().enumerate() {
+        let leg_accounts = leg_accounts(remaining_accounts, deal, index, leg)?;
+        release_leg(&leg_accounts, maker.key, maker, controller, hook_accounts, signer_seeds)?;
+    }
+    Ok(())
+}
+
+fn mint_decimals(mint: &AccountInfo) -> Result<u8> {
+    let mint_data = mint.try_borrow_data()?;
+    Ok(StateWithExtensions::<MintState>::unpack(&mint_data)?.base.decimals)
+}
+
+// owner and amount of a token account of either token program
+fn token_account_state(token_acc: &AccountInfo) -> Result<(Pubkey, u64)> {
+    let token_acc_data = token_acc.try_borrow_data()?;
+    let token_acc_state = StateWithExtensions::<TokenAccountState>::unpack(&token_acc_data)?;
+    Ok((token_acc_state.base.owner, token_acc_state.base.amount))
+}
@@ -1,4 +1,32 @@
 use anchor_lang::prelude::*;
 
 #[constant]
-pub const SEED: &str = "anchor";
\ No newline at end of file
+pub const SEED: &str = "anchor";
+
+// extra (mint, amount) legs each side of a basket deal can carry next to mint_a / mint_b
+#[constant]
+pub const MAX_BASKET_LEGS: u8 = 3;
+
+// split_bps handed to resolve_dispute is out of this many basis points
+#[constant]
+pub const BPS_DENOMINATOR: u16 = 10_000;
+
+// most tranches the maker side of a milestone deal can be split into
+#[constant]
+pub const MAX_MILESTONES: u8 = 8;
+
+// oldest oracle price in seconds a price conditional deal still fills against
+#[constant]
+pub const MAX_PRICE_AGE: i64 = 60;
+
+// wallets a deal's taker allowlist can name directly, larger groups go through a Merkle root
+#[constant]
+pub const MAX_ALLOWED_TAKERS: u8 = 8;
+
+// prefix of every signed offer message, together with the program id it keeps a signature from being valid anywhere else
+#[constant]
+pub const SIGNED_OFFER_DOMAIN: &[u8] = b"escrow-offer";
+
+// open deals a single page of a (mint_a, mint_b) order index can list at once
+#[constant]
+pub const MAX_INDEXED_DEALS: u8 = 64;
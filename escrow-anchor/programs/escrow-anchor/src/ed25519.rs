@@ -0,0 +1,39 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::ed25519_program;
+use anchor_lang::solana_program::sysvar::instructions::{load_current_index_checked, load_instruction_at_checked};
+
+use crate::ErrorCode;
+
+// Layout of an Ed25519 program instruction: signature count, a padding byte, then per signature
+// seven u16 offsets (signature, its instruction, public key, its instruction, message, message size, its instruction)
+const OFFSETS_START: usize = 2;
+const OFFSETS_LEN: usize = 14;
+const PUBKEY_LEN: usize = 32;
+// instruction index meaning "inside this same Ed25519 instruction"
+const THIS_INSTRUCTION: u16 = u16::MAX;
+
+// The runtime already verified the signature in the Ed25519 instruction right before this one, all that is
+// left is making sure it was over `message` and by `signer`
+pub(crate) fn require_ed25519_signature(instructions_sysvar: &AccountInfo, signer: &Pubkey, message: &[u8]) -> Result<()> {
+    let current_index = load_current_index_checked(instructions_sysvar)?;
+    require!(current_index > 0, ErrorCode::InvalidSignature);
+    let ed25519_ix = load_instruction_at_checked(current_index as usize - 1, instructions_sysvar)?;
+    require_keys_eq!(ed25519_ix.program_id, ed25519_program::ID, ErrorCode::InvalidSignature);
+
+    let data = &ed25519_ix.data;
+    require!(data.len() >= OFFSETS_START + OFFSETS_LEN && data[0] == 1, ErrorCode::InvalidSignature);
+    let read_u16 = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+    let offset = |field: usize| read_u16(OFFSETS_START + field * 2);
+    let (signature_ix, pubkey_offset, pubkey_ix) = (offset(1), offset(2) as usize, offset(3));
+    let (message_offset, message_size, message_ix) = (offset(4) as usize, offset(5) as usize, offset(6));
+
+    // a signature over data pulled from another instruction could be over anything
+    require!(
+        signature_ix == THIS_INSTRUCTION && pubkey_ix == THIS_INSTRUCTION && message_ix == THIS_INSTRUCTION,
+        ErrorCode::InvalidSignature
+    );
+    let pubkey = data.get(pubkey_offset..pubkey_offset + PUBKEY_LEN).ok_or(ErrorCode::InvalidSignature)?;
+    let signed_message = data.get(message_offset..message_offset + message_size).ok_or(ErrorCode::InvalidSignature)?;
+    require!(pubkey == signer.as_ref() && signed_message == message, ErrorCode::InvalidSignature);
+    Ok(())
+}
@@ -2,9 +2,6 @@ use anchor_lang::prelude::*;
 
 #[error_code]
 pub enum ErrorCode {
-    #[msg("Custom error message")]
-    CustomError,
-
     #[msg("Either both or a single party has yet to pay for their part of the transaction")]
     IncompleteDeal,
 
@@ -12,5 +9,149 @@ pub enum ErrorCode {
     InvalidUser,
 
     #[msg("Unable to clear account, it still contains funds")]
-    AccountContainsFund
-}
\ No newline at end of file
+    AccountContainsFund,
+
+    #[msg("Deal has already been fulfilled")]
+    DealAlreadyFulfilled,
+
+    #[msg("Deal expiry has to be in the future")]
+    InvalidExpiry,
+
+    #[msg("Deal has expired")]
+    DealExpired,
+
+    #[msg("Deal has not expired yet")]
+    DealNotExpired,
+
+    #[msg("Fill amount has to be above zero, within what is left on the deal and worth at least one unit of mint_a")]
+    InvalidFillAmount,
+
+    #[msg("Deal has already been partially filled")]
+    DealPartiallyFilled,
+
+    #[msg("Instruction is not allowed in the deal's current state")]
+    InvalidDealState,
+
+    #[msg("Party has already withdrawn everything it is owed")]
+    AlreadyClaimed,
+
+    #[msg("Mint does not match the one recorded for this deal")]
+    InvalidMint,
+
+    #[msg("Both sides of a deal have to use different mints")]
+    IdenticalMints,
+
+    #[msg("Deal amounts have to be above zero")]
+    InvalidAmount,
+
+    #[msg("Token accounts are required for a token leg of the deal")]
+    MissingTokenAccount,
+
+    #[msg("Token program does not match the one that owns this leg's mint")]
+    InvalidTokenProgram,
+
+    #[msg("Mint is non-transferable, escrowed tokens could never be released")]
+    NonTransferableMint,
+
+    #[msg("Mint freezes new token accounts by default, the escrow account would be frozen")]
+    FrozenByDefaultMint,
+
+    #[msg("Mint has a permanent delegate that could move tokens out of the escrow")]
+    PermanentDelegateMint,
+
+    #[msg("Mint supports confidential transfers, escrowed balances could not be verified")]
+    ConfidentialTransferMint,
+
+    #[msg("Deal already carries the maximum number of basket legs on this side")]
+    BasketFull,
+
+    #[msg("Basket legs can only be added before anyone fills the deal")]
+    BasketLocked,
+
+    #[msg("Basket deals have to be filled in a single deposit")]
+    BasketRequiresFullFill,
+
+    #[msg("Accounts of every basket leg are required, in leg order")]
+    MissingBasketAccounts,
+
+    #[msg("Account does not belong to this basket leg")]
+    InvalidBasketAccount,
+
+    #[msg("Basket deals settle through deposit and withdraw")]
+    BasketNotSupported,
+
+    #[msg("An arbiter can only be set on a deal with a fixed taker, and has to be someone other than maker and taker")]
+    InvalidArbiter,
+
+    #[msg("Deal has no arbiter to settle a dispute")]
+    NoArbiter,
+
+    #[msg("Deal is frozen while a dispute is open")]
+    DisputeOpen,
+
+    #[msg("Deal has no open dispute")]
+    NoDisputeOpen,
+
+    #[msg("Split has to be between 0 and 10000 basis points")]
+    InvalidSplit,
+
+    #[msg("Milestones have to be above zero, at most MAX_MILESTONES and add up to the maker amount")]
+    InvalidMilestones,
+
+    #[msg("Milestones can only be set on a deal with a fixed taker")]
+    MilestonesRequireTaker,
+
+    #[msg("Milestone deals have to be filled in a single deposit")]
+    MilestonesRequireFullFill,
+
+    #[msg("Milestones are approved one at a time, in order")]
+    MilestoneOutOfOrder,
+
+    #[msg("Milestone deals release mint_a through approve_milestone")]
+    MilestoneDeal,
+
+    #[msg("Minimum price has to be at most the maximum price")]
+    InvalidPriceCondition,
+
+    #[msg("Oracle account does not match the deal's price condition or has an unexpected layout")]
+    InvalidOracle,
+
+    #[msg("Oracle price is older than MAX_PRICE_AGE")]
+    StalePrice,
+
+    #[msg("Oracle price is outside the deal's price range")]
+    PriceOutOfRange,
+
+    #[msg("Deal terms changed from what the taker expected")]
+    TermsChanged,
+
+    #[msg("Allowlist has to name 1 to MAX_ALLOWED_TAKERS takers and can only be set on an open offer")]
+    InvalidAllowlist,
+
+    #[msg("Taker is not on the deal's allowlist")]
+    TakerNotAllowed,
+
+    #[msg("Offer has to be signed by its maker in an Ed25519 program instruction right before this one")]
+    InvalidSignature,
+
+    #[msg("Order index does not match the deal's mints or is not the one the deal is listed in")]
+    InvalidOrderIndex,
+
+    #[msg("Deal is listed in an order index that has to be passed in")]
+    MissingOrderIndex,
+
+    #[msg("Order index page already lists MAX_INDEXED_DEALS deals, list the deal on another page")]
+    OrderIndexFull,
+
+    #[msg("Only open offers without a fixed taker can be listed in an order index")]
+    IndexRequiresOpenOffer,
+
+    #[msg("Only open offers without a fixed taker can be bound to their first taker")]
+    BindingRequiresOpenOffer,
+
+    #[msg("Milestones and basket legs can't be combined on the same deal")]
+    MilestonesWithBasket,
+
+    #[msg("Oracle price is published ahead of the cluster clock")]
+    FuturePrice
+}
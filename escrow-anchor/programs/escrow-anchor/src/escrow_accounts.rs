@@ -0,0 +1,169 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_2022::{close_account, CloseAccount};
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface, TransferChecked};
+
+use crate::extensions::transfer_checked_with_hook;
+use crate::native::{is_native_mint, sweep_controller, transfer_from_controller, unwrap_to, UnwrapAccounts};
+use crate::{DealDetails, ErrorCode, UserEscrowDetails};
+
+// Helpers around the escrow accounts of a deal and the escrow_token_controller PDA that owns them, shared by every
+// instruction that pays out of or closes a deal
+
+// Seeds escrow_token_controller signs with. Holds the deal id bytes and bump so the signer seeds can borrow them
+pub(crate) struct ControllerSeeds {
+    maker: Pubkey,
+    deal_id: [u8; 8],
+    bump: [u8; 1],
+}
+
+impl ControllerSeeds {
+    pub(crate) fn new(deal_details: &DealDetails) -> Self {
+        Self {
+            maker: deal_details.maker,
+            deal_id: deal_details.deal_id.to_le_bytes(),
+            bump: [deal_details.escrow_token_controller_bump],
+        }
+    }
+
+    pub(crate) fn seeds(&self) -> [&[u8]; 4] {
+        [b"controller", self.maker.as_ref(), &self.deal_id, &self.bump]
+    }
+}
+
+// Last step of every instruction that ends a deal, closes the escrow of each leg and returns everything left in
+// the controller to the maker. A native SOL leg has no escrow, its lamports go out with the controller
+pub(crate) fn close_escrows<'info>(
+    escrows: [(&Option<InterfaceAccount<'info, TokenAccount>>, &Interface<'info, TokenInterface>); 2],
+    maker: &AccountInfo<'info>,
+    controller: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    for (escrow_token_acc, token_program) in escrows {
+        if let Some(escrow_token_acc) = escrow_token_acc {
+            close_token_account(escrow_token_acc, maker, controller, token_program, signer_seeds)?;
+        }
+    }
+    sweep_controller(controller, maker, system_program, signer_seeds)
+}
+
+// Closes a token account the controller owns, its rent goes to destination
+pub(crate) fn close_token_account<'info>(
+    token_acc: &InterfaceAccount<'info, TokenAccount>,
+    destination: &AccountInfo<'info>,
+    authority: &AccountInfo<'info>,
+    token_program: &Interface<'info, TokenInterface>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    let cpi_accounts = CloseAccount {
+        account: token_acc.to_account_info(),
+        destination: destination.to_account_info(),
+        authority: authority.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        cpi_accounts,
+        signer_seeds,
+    );
+    close_account(cpi_ctx)
+}
+
+// Hands whatever is left in an escrow to the maker so it can be closed. Nothing in it is owed to anyone else by then,
+// any balance is mint_b the maker never collected or tokens sent in on top of the deal. wSOL needs no sweep,
+// closing the escrow pays its lamports out to the maker
+pub(crate) fn sweep_escrow<'info>(
+    escrow_token_acc: &InterfaceAccount<'info, TokenAccount>,
+    mint: Option<&InterfaceAccount<'info, Mint>>,
+    maker_token_acc: Option<&InterfaceAccount<'info, TokenAccount>>,
+    controller: &AccountInfo<'info>,
+    token_program: &Interface<'info, TokenInterface>,
+    signer_seeds: &[&[&[u8]]],
+    hook_accounts: &[AccountInfo<'info>],
+) -> Result<()> {
+    if escrow_token_acc.amount == 0 || is_native_mint(&escrow_token_acc.mint) {
+        return Ok(());
+    }
+    let mint = mint.ok_or(ErrorCode::InvalidMint)?;
+    let cpi_accounts = TransferChecked {
+        mint: mint.to_account_info(),
+        from: escrow_token_acc.to_account_info(),
+        to: maker_token_acc.ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+        authority: controller.to_account_info(),
+    };
+    let cpi_context = CpiContext::new(token_program.to_account_info(), cpi_accounts).with_signer(signer_seeds).with_remaining_accounts(hook_accounts.to_vec());
+    transfer_checked_with_hook(cpi_context, escrow_token_acc.amount, mint.decimals)
+}
+
+// Mint of a leg as recorded in its details, None for a native SOL leg. The mint account is optional in the
+// instructions and its address constraint is skipped when left out, so whether a leg is paid in tokens or
+// lamports has to come from the details, a token leg without its mint is rejected
+pub(crate) fn leg_mint<'a, 'info>(
+    mint: &'a Option<InterfaceAccount<'info, Mint>>,
+    user_details: &UserEscrowDetails,
+) -> Result<Option<&'a InterfaceAccount<'info, Mint>>> {
+    if user_details.is_native() {
+        require!(mint.is_none(), ErrorCode::InvalidMint);
+        Ok(None)
+    } else {
+        Ok(Some(mint.as_ref().ok_or(ErrorCode::InvalidMint)?))
+    }
+}
+
+// Accounts the maker side of a deal is refunded through. unwrap_token_acc and its bump are only needed for a wSOL
+// leg, payer fronts the rent of that throwaway account and gets it back in the same instruction
+pub(crate) struct MakerRefund<'a, 'info> {
+    pub maker: AccountInfo<'info>,
+    pub payer: AccountInfo<'info>,
+    pub deal: Pubkey,
+    pub mint_a: &'a Option<InterfaceAccount<'info, Mint>>,
+    pub user_a_details: &'a UserEscrowDetails,
+    pub escrow_token_acc_a: &'a Option<InterfaceAccount<'info, TokenAccount>>,
+    pub maker_token_acc_a: &'a Option<InterfaceAccount<'info, TokenAccount>>,
+    pub unwrap_token_acc: Option<AccountInfo<'info>>,
+    pub unwrap_bump: Option<u8>,
+    pub controller: AccountInfo<'info>,
+    pub token_program_a: &'a Interface<'info, TokenInterface>,
+    pub system_program: AccountInfo<'info>,
+}
+
+// Pays mint_a held for the deal back to the maker. An auto-wrapped maker has no wSOL account to refund to,
+// so wSOL goes back as lamports, and a native SOL leg is paid out of the controller
+pub(crate) fn refund_maker_leg_a<'info>(
+    accounts: &MakerRefund<'_, 'info>,
+    controller_seeds: &[&[&[u8]]],
+    hook_accounts: &[AccountInfo<'info>],
+    amount: u64,
+) -> Result<()> {
+    match leg_mint(accounts.mint_a, accounts.user_a_details)? {
+        Some(mint_a) if is_native_mint(&mint_a.key()) => {
+            let unwrap_accounts = UnwrapAccounts {
+                payer: accounts.payer.clone(),
+                unwrap_token_acc: accounts.unwrap_token_acc.clone().ok_or(ErrorCode::MissingTokenAccount)?,
+                mint: mint_a.to_account_info(),
+                escrow_token_acc: accounts.escrow_token_acc_a.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+                controller: accounts.controller.clone(),
+                token_program: accounts.token_program_a.to_account_info(),
+                system_program: accounts.system_program.clone(),
+            };
+            unwrap_to(
+                &unwrap_accounts,
+                &accounts.maker,
+                &accounts.deal,
+                accounts.unwrap_bump.ok_or(ErrorCode::MissingTokenAccount)?,
+                controller_seeds,
+                amount,
+            )
+        }
+        Some(mint_a) => {
+            let cpi_accounts = TransferChecked {
+                mint: mint_a.to_account_info(),
+                from: accounts.escrow_token_acc_a.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+                to: accounts.maker_token_acc_a.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+                authority: accounts.controller.clone(),
+            };
+            let cpi_context = CpiContext::new(accounts.token_program_a.to_account_info(), cpi_accounts).with_signer(controller_seeds).with_remaining_accounts(hook_accounts.to_vec());
+            transfer_checked_with_hook(cpi_context, amount, mint_a.decimals)
+        }
+        None => transfer_from_controller(&accounts.controller, &accounts.maker, &accounts.system_program, controller_seeds, amount),
+    }
+}
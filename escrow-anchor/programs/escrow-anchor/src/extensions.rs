@@ -0,0 +1,89 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{
+        default_account_state::DefaultAccountState, permanent_delegate::PermanentDelegate,
+        transfer_fee::TransferFeeConfig, BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+    },
+    state::{AccountState, Mint as MintState},
+};
+use anchor_spl::token_interface::{Mint, TransferChecked};
+
+use crate::ErrorCode;
+
+// Helpers around Token-2022 mint extensions. Mints without the extension, SPL Token mints included, behave as before
+
+// Rejects mints that could never leave the escrow, or that someone else could move out of it
+pub(crate) fn require_escrowable_mint(mint: &InterfaceAccount<Mint>) -> Result<()> {
+    let mint_info = mint.to_account_info();
+    let mint_data = mint_info.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<MintState>::unpack(&mint_data)?;
+
+    for extension_type in mint_state.get_extension_types()? {
+        match extension_type {
+            ExtensionType::NonTransferable => return err!(ErrorCode::NonTransferableMint),
+            ExtensionType::ConfidentialTransferMint => return err!(ErrorCode::ConfidentialTransferMint),
+            ExtensionType::DefaultAccountState => {
+                let default_state = mint_state.get_extension::<DefaultAccountState>()?;
+                require!(default_state.state != AccountState::Frozen as u8, ErrorCode::FrozenByDefaultMint);
+            }
+            ExtensionType::PermanentDelegate => {
+                let permanent_delegate = mint_state.get_extension::<PermanentDelegate>()?;
+                require!(
+                    Option::<Pubkey>::from(permanent_delegate.delegate).is_none(),
+                    ErrorCode::PermanentDelegateMint
+                );
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Fee a transfer of `amount` is charged under the mint's TransferFeeConfig
+pub(crate) fn transfer_fee(mint_info: &AccountInfo, amount: u64) -> Result<u64> {
+    let mint_data = mint_info.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<MintState>::unpack(&mint_data)?;
+
+    match mint_state.get_extension::<TransferFeeConfig>() {
+        Ok(fee_config) => Ok(fee_config
+            .calculate_epoch_fee(Clock::get()?.epoch, amount)
+            .ok_or(ErrorCode::InvalidAmount)?),
+        Err(_) => Ok(0),
+    }
+}
+
+// What has to be sent so that `net_amount` still arrives after the transfer fee is withheld
+pub(crate) fn gross_amount(mint_info: &AccountInfo, net_amount: u64) -> Result<u64> {
+    let mint_data = mint_info.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<MintState>::unpack(&mint_data)?;
+
+    let fee = match mint_state.get_extension::<TransferFeeConfig>() {
+        Ok(fee_config) => fee_config
+            .calculate_inverse_epoch_fee(Clock::get()?.epoch, net_amount)
+            .ok_or(ErrorCode::InvalidAmount)?,
+        Err(_) => 0,
+    };
+    Ok(net_amount.checked_add(fee).ok_or(ErrorCode::InvalidAmount)?)
+}
+
+// transfer_checked that also hands a TransferHook mint the extra accounts it asks for.
+// Callers pass the hook program, its extra-account-metas PDA and whatever those metas list through remaining_accounts
+pub(crate) fn transfer_checked_with_hook<'info>(
+    ctx: CpiContext<'_, '_, '_, 'info, TransferChecked<'info>>,
+    amount: u64,
+    decimals: u8,
+) -> Result<()> {
+    spl_token_2022::onchain::invoke_transfer_checked(
+        ctx.program.key,
+        ctx.accounts.from,
+        ctx.accounts.mint,
+        ctx.accounts.to,
+        ctx.accounts.authority,
+        &ctx.remaining_accounts,
+        amount,
+        decimals,
+        ctx.signer_seeds,
+    )
+    .map_err(Into::into)
+}
@@ -0,0 +1,145 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface, TransferChecked};
+
+use crate::extensions::{gross_amount, transfer_checked_with_hook};
+use crate::escrow_accounts::{leg_mint, refund_maker_leg_a, ControllerSeeds, MakerRefund};
+use crate::native::{is_native_mint, transfer_to_controller, wrap_into_escrow};
+use crate::order_index::unindex_deal;
+use crate::{DealDetails, DealState, ErrorCode, OrderIndex, Proposal, UserEscrowDetails};
+
+// Maker takes a counter offer, the escrow is topped up or partly refunded so it holds the new maker amount
+// and both sides of the deal are repriced in the same instruction
+#[derive(Accounts)]
+#[instruction(deal_id: u64)]
+pub struct AcceptCounter<'info> {
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    /// CHECK: checked against the proposal, receives its rent
+    #[account(mut, address = proposal.taker @ ErrorCode::InvalidUser)]
+    pub taker: AccountInfo<'info>,
+
+    #[account(mut, seeds=[b"deal", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.deal_details_bump)]
+    pub deal_details: Account<'info, DealDetails>,
+
+    #[account(
+        mut,
+        seeds=[b"proposal", deal_details.key().as_ref(), taker.key().as_ref()],
+        bump=proposal.proposal_bump,
+        close=taker
+    )]
+    pub proposal: Account<'info, Proposal>,
+
+    // holds the lamports of a native SOL leg
+    #[account(mut, seeds=[b"controller", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.escrow_token_controller_bump)]
+    pub escrow_token_controller: SystemAccount<'info>,
+
+    // mint and token accounts of a native SOL leg are left out
+    #[account(address = user_a_details.mint @ ErrorCode::InvalidMint, mint::token_program = token_program_a)]
+    pub mint_a: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut, seeds=[b"user_a_details", deal_details.key().as_ref()], bump=user_a_details.user_details_bump)]
+    pub user_a_details: Account<'info, UserEscrowDetails>,
+
+    #[account(mut, seeds=[b"user_b_details", deal_details.key().as_ref()], bump=user_b_details.user_details_bump)]
+    pub user_b_details: Account<'info, UserEscrowDetails>,
+
+    #[account(mut, seeds=[b"token_a", deal_details.key().as_ref()], bump=user_a_details.escrow_token_acc_bump)]
+    pub escrow_token_acc_a: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // tops up the escrow or takes the refund, not needed for a native SOL or wSOL top up or refund
+    #[account(
+        mut,
+        associated_token::mint = mint_a,
+        associated_token::authority = maker,
+        associated_token::token_program = token_program_a
+    )]
+    pub maker_token_acc_a: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: created and closed again within the instruction to unwrap a wSOL refund, only needed for a wSOL maker side
+    #[account(mut, seeds=[b"unwrap", deal_details.key().as_ref(), maker.key().as_ref()], bump)]
+    pub unwrap_token_acc: Option<UncheckedAccount<'info>>,
+
+    // only when an open offer is listed in the order index of its mint pair, accepting binds it to the proposer
+    #[account(mut)]
+    pub order_index: Option<Account<'info, OrderIndex>>,
+
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+pub fn handler<'info>(ctx: Context<'_, '_, '_, 'info, AcceptCounter<'info>>) -> Result<()> {
+    require!(
+        Clock::get()?.unix_timestamp < ctx.accounts.deal_details.expires_at,
+        ErrorCode::DealExpired
+    );
+    require!(ctx.accounts.deal_details.state == DealState::Created, ErrorCode::InvalidDealState);
+    // milestones were split for the old amount, they would no longer add up
+    require!(ctx.accounts.user_a_details.milestones.is_empty(), ErrorCode::InvalidMilestones);
+
+    let current_maker_amt = ctx.accounts.user_a_details.mint_amt;
+    let new_maker_amt = ctx.accounts.proposal.maker_amt;
+    let new_taker_amt = ctx.accounts.proposal.taker_amt;
+
+    if new_maker_amt > current_maker_amt {
+        let top_up = new_maker_amt - current_maker_amt;
+        match leg_mint(&ctx.accounts.mint_a, &ctx.accounts.user_a_details)? {
+            Some(mint_a) if is_native_mint(&mint_a.key()) => wrap_into_escrow(
+                &ctx.accounts.maker.to_account_info(),
+                ctx.accounts.escrow_token_acc_a.as_ref().ok_or(ErrorCode::MissingTokenAccount)?,
+                &ctx.accounts.token_program_a,
+                &ctx.accounts.system_program.to_account_info(),
+                top_up,
+            )?,
+            Some(mint_a) => {
+                let cpi_accounts = TransferChecked {
+                    mint: mint_a.to_account_info(),
+                    from: ctx.accounts.maker_token_acc_a.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+                    to: ctx.accounts.escrow_token_acc_a.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+                    authority: ctx.accounts.maker.to_account_info(),
+                };
+                let cpi_context = CpiContext::new(ctx.accounts.token_program_a.to_account_info(), cpi_accounts).with_remaining_accounts(ctx.remaining_accounts.to_vec());
+                transfer_checked_with_hook(cpi_context, gross_amount(mint_a.as_ref(), top_up)?, mint_a.decimals)?;
+            }
+            None => transfer_to_controller(
+                &ctx.accounts.maker.to_account_info(),
+                &ctx.accounts.escrow_token_controller.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+                top_up,
+            )?,
+        }
+    } else if new_maker_amt < current_maker_amt {
+        let refund = current_maker_amt - new_maker_amt;
+        let controller_signer = ControllerSeeds::new(&ctx.accounts.deal_details);
+        let controller_seeds: &[&[&[u8]]] = &[&controller_signer.seeds()];
+
+        let maker_refund = MakerRefund {
+            maker: ctx.accounts.maker.to_account_info(),
+            payer: ctx.accounts.maker.to_account_info(),
+            deal: ctx.accounts.deal_details.key(),
+            mint_a: &ctx.accounts.mint_a,
+            user_a_details: &ctx.accounts.user_a_details,
+            escrow_token_acc_a: &ctx.accounts.escrow_token_acc_a,
+            maker_token_acc_a: &ctx.accounts.maker_token_acc_a,
+            unwrap_token_acc: ctx.accounts.unwrap_token_acc.as_ref().map(|account| account.to_account_info()),
+            unwrap_bump: ctx.bumps.unwrap_token_acc,
+            controller: ctx.accounts.escrow_token_controller.to_account_info(),
+            token_program_a: &ctx.accounts.token_program_a,
+            system_program: ctx.accounts.system_program.to_account_info(),
+        };
+        refund_maker_leg_a(&maker_refund, controller_seeds, ctx.remaining_accounts, refund)?;
+    }
+
+    ctx.accounts.user_a_details.mint_amt = new_maker_amt;
+    ctx.accounts.user_b_details.mint_amt = new_taker_amt;
+    ctx.accounts.deal_details.maker_amt_remaining = new_maker_amt;
+    ctx.accounts.deal_details.taker_amt_remaining = new_taker_amt;
+    // the terms were negotiated by the proposer, nobody else gets to fill at them
+    if ctx.accounts.deal_details.taker.is_none() {
+        ctx.accounts.deal_details.taker = Some(ctx.accounts.proposal.taker);
+        unindex_deal(&mut ctx.accounts.deal_details, &mut ctx.accounts.order_index)?;
+    }
+
+    msg!("Deal {:?} repriced to {:?} for {:?}", ctx.accounts.deal_details.deal_id, new_maker_amt, new_taker_amt);
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface, TransferChecked};
+
+use crate::extensions::{gross_amount, require_escrowable_mint, transfer_checked_with_hook};
+use crate::{BasketDetails, BasketLeg, DealDetails, DealState, ErrorCode, LegSide, UserEscrowDetails, MAX_BASKET_LEGS};
+
+// Turns a deal into a basket, each call adds one more (mint, amount) leg to either side.
+// Maker legs are escrowed straight away, taker legs are paid in together with the rest of the deposit
+#[derive(Accounts)]
+#[instruction(deal_id: u64)]
+pub struct AddLeg<'info> {
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    #[account(mut, seeds=[b"deal", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.deal_details_bump)]
+    pub deal_details: Account<'info, DealDetails>,
+
+    #[account(
+        init_if_needed,
+        payer=maker,
+        seeds=[b"basket", deal_details.key().as_ref()],
+        space=8+BasketDetails::INIT_SPACE,
+        bump
+    )]
+    pub basket_details: Account<'info, BasketDetails>,
+
+    #[account(seeds=[b"controller", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.escrow_token_controller_bump)]
+    pub escrow_token_controller: SystemAccount<'info>,
+
+    #[account(mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    // legs are numbered in the order they are added, the index is part of the escrow seeds
+    #[account(
+        init,
+        payer=maker,
+        seeds=[b"leg", deal_details.key().as_ref(), &[deal_details.basket_legs]],
+        token::mint = mint,
+        token::authority = escrow_token_controller,
+        token::token_program = token_program,
+        bump
+    )]
+    pub leg_escrow_token_acc: InterfaceAccount<'info, TokenAccount>,
+
+    // only needed for a maker leg, the account it is funded from
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = maker,
+        associated_token::token_program = token_program
+    )]
+    pub maker_token_acc: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(seeds=[b"user_a_details", deal_details.key().as_ref()], bump=user_a_details.user_details_bump)]
+    pub user_a_details: Account<'info, UserEscrowDetails>,
+
+    #[account(seeds=[b"user_b_details", deal_details.key().as_ref()], bump=user_b_details.user_details_bump)]
+    pub user_b_details: Account<'info, UserEscrowDetails>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+pub fn handler<'info>(ctx: Context<'_, '_, '_, 'info, AddLeg<'info>>, side: LegSide, amount: u64) -> Result<()> {
+    require!(
+        Clock::get()?.unix_timestamp < ctx.accounts.deal_details.expires_at,
+        ErrorCode::DealExpired
+    );
+    // the terms every taker fills against must not change under them
+    require!(ctx.accounts.deal_details.state == DealState::Created, ErrorCode::BasketLocked);
+    require!(amount > 0, ErrorCode::InvalidAmount);
+    // legs are released in full on the first withdraw, a milestone deal pays its taker out over several
+    require!(ctx.accounts.user_a_details.milestones.is_empty(), ErrorCode::MilestonesWithBasket);
+    require!(
+        ctx.accounts.basket_details.side_count(side) < MAX_BASKET_LEGS as usize,
+        ErrorCode::BasketFull
+    );
+
+    // one escrow per mint, a mint already traded in the deal is topped up through its own amount instead
+    let mint_key = ctx.accounts.mint.key();
+    require!(
+        mint_key != ctx.accounts.user_a_details.mint
+            && mint_key != ctx.accounts.user_b_details.mint
+            && ctx.accounts.basket_details.legs.iter().all(|leg| leg.mint != mint_key),
+        ErrorCode::IdenticalMints
+    );
+    require_escrowable_mint(&ctx.accounts.mint)?;
+
+    if side == LegSide::Maker {
+        let cpi_accounts = TransferChecked {
+            mint: ctx.accounts.mint.to_account_info(),
+            from: ctx.accounts.maker_token_acc.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+            to: ctx.accounts.leg_escrow_token_acc.to_account_info(),
+            authority: ctx.accounts.maker.to_account_info(),
+        };
+        let cpi_context = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts).with_remaining_accounts(ctx.remaining_accounts.to_vec());
+        transfer_checked_with_hook(cpi_context, gross_amount(ctx.accounts.mint.as_ref(), amount)?, ctx.accounts.mint.decimals)?;
+    }
+
+    ctx.accounts.basket_details.basket_details_bump = ctx.bumps.basket_details;
+    ctx.accounts.basket_details.legs.push(BasketLeg {
+        side,
+        mint: mint_key,
+        mint_amt: amount,
+        escrow_token_acc_bump: ctx.bumps.leg_escrow_token_acc,
+    });
+    ctx.accounts.deal_details.basket_legs += 1;
+
+    msg!("Added {:?} leg {:?} of {:?}", side, ctx.accounts.deal_details.basket_legs - 1, amount);
+    Ok(())
+}
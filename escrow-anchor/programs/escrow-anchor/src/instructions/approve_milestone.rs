@@ -0,0 +1,45 @@
+use anchor_lang::prelude::*;
+
+use crate::{DealDetails, ErrorCode, UserEscrowDetails};
+
+// Releases the next tranche of the maker side to the taker, who can withdraw it straight after.
+// Approval comes from the side paying the tranche or the arbiter, never from the taker receiving it
+#[derive(Accounts)]
+#[instruction(deal_id: u64)]
+pub struct ApproveMilestone<'info> {
+    // the deal's maker or its arbiter
+    pub signer: Signer<'info>,
+
+    /// CHECK: just used as a public key wallet to derive the deal
+    pub maker: AccountInfo<'info>,
+
+    #[account(seeds=[b"deal", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.deal_details_bump)]
+    pub deal_details: Account<'info, DealDetails>,
+
+    #[account(mut, seeds=[b"user_a_details", deal_details.key().as_ref()], bump=user_a_details.user_details_bump)]
+    pub user_a_details: Account<'info, UserEscrowDetails>,
+}
+
+pub fn handler(ctx: Context<ApproveMilestone>, index: u8) -> Result<()> {
+    let deal_details = &ctx.accounts.deal_details;
+    let signer = ctx.accounts.signer.key();
+    require!(
+        signer == deal_details.maker || Some(signer) == deal_details.arbiter,
+        ErrorCode::InvalidUser
+    );
+    require!(!deal_details.dispute_open, ErrorCode::DisputeOpen);
+    // nothing to release before the taker has paid for it
+    require!(deal_details.state.is_filled(), ErrorCode::IncompleteDeal);
+
+    let user_a_details = &mut ctx.accounts.user_a_details;
+    require!(
+        index == user_a_details.milestones_released && (index as usize) < user_a_details.milestones.len(),
+        ErrorCode::MilestoneOutOfOrder
+    );
+
+    user_a_details.released_amt += user_a_details.milestones[index as usize];
+    user_a_details.milestones_released += 1;
+
+    msg!("Milestone {:?} approved, {:?} left to release", index, user_a_details.remaining_amt());
+    Ok(())
+}
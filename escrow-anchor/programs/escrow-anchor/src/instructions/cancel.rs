@@ -0,0 +1,152 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+
+use crate::basket::{basket_legs, hook_accounts, refund_basket};
+use crate::escrow_accounts::{close_escrows, sweep_escrow, refund_maker_leg_a, ControllerSeeds, MakerRefund};
+use crate::native::native_escrow_amount;
+use crate::order_index::unindex_deal;
+use crate::{BasketDetails, DealDetails, DealState, ErrorCode, OrderIndex, UserEscrowDetails};
+
+// Lets the maker back out of a deal nobody has filled yet, mint_a is refunded and every deal account is closed
+#[derive(Accounts)]
+#[instruction(deal_id: u64)]
+pub struct Cancel<'info> {
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    #[account(mut, seeds=[b"deal", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.deal_details_bump, close=maker)]
+    pub deal_details: Account<'info, DealDetails>,
+
+    #[account(mut, seeds=[b"controller", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.escrow_token_controller_bump)]
+    pub escrow_token_controller: SystemAccount<'info>,
+
+    // mint and token accounts of a native SOL leg are left out, the refund is paid in lamports
+    #[account(address = user_a_details.mint @ ErrorCode::InvalidMint, mint::token_program = token_program_a)]
+    pub mint_a: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(address = user_b_details.mint @ ErrorCode::InvalidMint, mint::token_program = token_program_b)]
+    pub mint_b: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut, seeds=[b"user_a_details", deal_details.key().as_ref()], bump=user_a_details.user_details_bump, close=maker)]
+    pub user_a_details: Account<'info, UserEscrowDetails>,
+
+    #[account(mut, seeds=[b"user_b_details", deal_details.key().as_ref()], bump=user_b_details.user_details_bump, close=maker)]
+    pub user_b_details: Account<'info, UserEscrowDetails>,
+
+    #[account(mut, seeds=[b"token_a", deal_details.key().as_ref()], bump=user_a_details.escrow_token_acc_bump)]
+    pub escrow_token_acc_a: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, seeds=[b"token_b", deal_details.key().as_ref()], bump=user_b_details.escrow_token_acc_bump)]
+    pub escrow_token_acc_b: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // maker's account the deposit was originally made from, left out for wSOL which is refunded unwrapped
+    #[account(
+        mut,
+        associated_token::mint = mint_a,
+        associated_token::authority = maker,
+        associated_token::token_program = token_program_a
+    )]
+    pub maker_token_acc_a: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // only needed when someone sent mint_b into the escrow, it is swept here so the escrow can be closed
+    #[account(mut, token::mint = mint_b, token::authority = maker, token::token_program = token_program_b)]
+    pub maker_token_acc_b: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: created and closed again within the instruction to unwrap a wSOL refund, only needed for a wSOL maker side
+    #[account(mut, seeds=[b"unwrap", deal_details.key().as_ref(), maker.key().as_ref()], bump)]
+    pub unwrap_token_acc: Option<UncheckedAccount<'info>>,
+
+    // only for a basket deal, every leg is refunded to the maker's accounts passed in remaining_accounts
+    #[account(mut, close=maker)]
+    pub basket_details: Option<Account<'info, BasketDetails>>,
+
+    // only when the deal is listed in the order index of its mint pair
+    #[account(mut)]
+    pub order_index: Option<Account<'info, OrderIndex>>,
+
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+pub fn handler<'info>(ctx: Context<'_, '_, '_, 'info, Cancel<'info>>) -> Result<()> {
+    // once the taker has paid the deal can only be settled through withdraw
+    let state = ctx.accounts.deal_details.state;
+    require!(!state.is_filled(), ErrorCode::DealAlreadyFulfilled);
+    // escrow_token_acc_a holds mint_a owed to earlier takers once anyone filled part of the deal
+    require!(state != DealState::PartiallyFilled, ErrorCode::DealPartiallyFilled);
+    require!(state == DealState::Created, ErrorCode::InvalidDealState);
+
+    let controller_signer = ControllerSeeds::new(&ctx.accounts.deal_details);
+    let controller_seeds: &[&[&[u8]]] = &[&controller_signer.seeds()];
+
+    // every token escrow has to be passed in, the deal accounts are closed at the end
+    require!(
+        ctx.accounts.escrow_token_acc_b.is_some() || ctx.accounts.user_b_details.is_native(),
+        ErrorCode::MissingTokenAccount
+    );
+    let legs = basket_legs(&ctx.accounts.deal_details, &ctx.accounts.basket_details)?;
+    let hook_accounts = hook_accounts(ctx.remaining_accounts, legs);
+
+    // the whole escrow goes back, nobody filled any of it
+    let refund_amount = match &ctx.accounts.escrow_token_acc_a {
+        Some(escrow_token_acc_a) => escrow_token_acc_a.amount,
+        None if ctx.accounts.user_a_details.is_native() => native_escrow_amount(&ctx.accounts.escrow_token_controller)?,
+        None => return err!(ErrorCode::MissingTokenAccount),
+    };
+    if refund_amount > 0 {
+        let maker_refund = MakerRefund {
+            maker: ctx.accounts.maker.to_account_info(),
+            payer: ctx.accounts.maker.to_account_info(),
+            deal: ctx.accounts.deal_details.key(),
+            mint_a: &ctx.accounts.mint_a,
+            user_a_details: &ctx.accounts.user_a_details,
+            escrow_token_acc_a: &ctx.accounts.escrow_token_acc_a,
+            maker_token_acc_a: &ctx.accounts.maker_token_acc_a,
+            unwrap_token_acc: ctx.accounts.unwrap_token_acc.as_ref().map(|account| account.to_account_info()),
+            unwrap_bump: ctx.bumps.unwrap_token_acc,
+            controller: ctx.accounts.escrow_token_controller.to_account_info(),
+            token_program_a: &ctx.accounts.token_program_a,
+            system_program: ctx.accounts.system_program.to_account_info(),
+        };
+        refund_maker_leg_a(&maker_refund, controller_seeds, hook_accounts, refund_amount)?;
+    }
+
+    // nobody paid into escrow_token_acc_b yet, anything in it was sent in on top of the deal
+    if let Some(escrow_token_acc_b) = &ctx.accounts.escrow_token_acc_b {
+        sweep_escrow(
+            escrow_token_acc_b,
+            ctx.accounts.mint_b.as_ref(),
+            ctx.accounts.maker_token_acc_b.as_ref(),
+            &ctx.accounts.escrow_token_controller.to_account_info(),
+            &ctx.accounts.token_program_b,
+            controller_seeds,
+            hook_accounts,
+        )?;
+    }
+
+    refund_basket(
+        ctx.remaining_accounts,
+        &ctx.accounts.deal_details.key(),
+        legs,
+        &ctx.accounts.maker.to_account_info(),
+        &ctx.accounts.escrow_token_controller.to_account_info(),
+        controller_seeds,
+    )?;
+
+    close_escrows(
+        [
+            (&ctx.accounts.escrow_token_acc_a, &ctx.accounts.token_program_a),
+            (&ctx.accounts.escrow_token_acc_b, &ctx.accounts.token_program_b),
+        ],
+        &ctx.accounts.maker.to_account_info(),
+        &ctx.accounts.escrow_token_controller.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+        controller_seeds,
+    )?;
+
+    unindex_deal(&mut ctx.accounts.deal_details, &mut ctx.accounts.order_index)?;
+    ctx.accounts.deal_details.state = DealState::Cancelled;
+    msg!("Deal {:?} cancelled, refunded {:?} to maker", ctx.accounts.deal_details.deal_id, refund_amount);
+    Ok(())
+}
@@ -0,0 +1,31 @@
+use anchor_lang::prelude::*;
+
+use crate::OfferNonce;
+
+// Burns a nonce so an offer signed with it can no longer be taken, revoking the delegate cancels every offer at once.
+// expires_at is the expiry of the cancelled offer, the nonce can be closed once it has passed
+#[derive(Accounts)]
+#[instruction(nonce: u64)]
+pub struct CancelSignedOffer<'info> {
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    #[account(
+        init,
+        payer=maker,
+        seeds=[b"nonce", maker.key().as_ref(), nonce.to_le_bytes().as_ref()],
+        space=8+OfferNonce::INIT_SPACE,
+        bump
+    )]
+    pub offer_nonce: Account<'info, OfferNonce>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn handler(ctx: Context<CancelSignedOffer>, nonce: u64, expires_at: i64) -> Result<()> {
+    ctx.accounts.offer_nonce.offer_nonce_bump = ctx.bumps.offer_nonce;
+    ctx.accounts.offer_nonce.payer = ctx.accounts.maker.key();
+    ctx.accounts.offer_nonce.expires_at = expires_at;
+    msg!("Signed offer {:?} of {:?} cancelled", nonce, ctx.accounts.maker.key());
+    Ok(())
+}
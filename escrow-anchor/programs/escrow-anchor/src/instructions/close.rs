@@ -1,109 +1,109 @@
 use anchor_lang::{ prelude::*};
-use crate::{DealDetails, UserEscrowDetails};
-use anchor_spl::{ token_2022::{close_account, CloseAccount}, token_interface::{TokenAccount, TokenInterface}};
+use crate::escrow_accounts::{close_escrows, sweep_escrow, ControllerSeeds};
+use crate::order_index::unindex_deal;
+use crate::{DealDetails, DealState, ErrorCode, OrderIndex, UserEscrowDetails};
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 
 // This is a temporary function to check deal/escrow details. Ideally this functionality should be made on the frontend which would take 0 fees
 
 #[derive(Accounts)]
+#[instruction(deal_id: u64)]
 pub struct Close<'info> {
 
     /// CHECK: Public key representing the deal maker
     #[account(mut)]
     maker: Signer<'info>,
 
-    /// CHECK: Public key representing the deal maker
-    #[account()]
-    taker: AccountInfo<'info>,
-
-    #[account(mut, seeds=[b"deal", maker.key().as_ref()], bump=deal_details.deal_details_bump, close=maker)]
+    #[account(mut, seeds=[b"deal", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.deal_details_bump, close=maker)]
     pub deal_details: Account<'info, DealDetails>,
 
-    pub token_program: Interface<'info, TokenInterface>,
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
 
     #[account(
         mut,
-        seeds=[b"controller", maker.key().as_ref()], 
+        seeds=[b"controller", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], 
         bump=deal_details.escrow_token_controller_bump,
     )]
     pub escrow_token_controller: SystemAccount<'info>,
 
     // need to structure deal_details better to identify whose bump this is
-    #[account(mut, seeds=[b"token", maker.key().as_ref()], bump=user_a_details.escrow_token_acc_bump)]
-    pub escrow_token_acc_a: InterfaceAccount<'info, TokenAccount>,
+    // left out for a native SOL leg, its lamports are swept together with the controller
+    #[account(mut, seeds=[b"token_a", deal_details.key().as_ref()], bump=user_a_details.escrow_token_acc_bump)]
+    pub escrow_token_acc_a: Option<InterfaceAccount<'info, TokenAccount>>,
 
-    #[account(mut, seeds=[b"user_details", maker.key().as_ref()], bump=user_a_details.user_details_bump, close=maker)]
+    #[account(mut, seeds=[b"user_a_details", deal_details.key().as_ref()], bump=user_a_details.user_details_bump, close=maker)]
     pub user_a_details: Account<'info, UserEscrowDetails>,
 
-    #[account(mut, seeds=[b"token", taker.key().as_ref()], bump=user_b_details.escrow_token_acc_bump)]
-    pub escrow_token_acc_b: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, seeds=[b"token_b", deal_details.key().as_ref()], bump=user_b_details.escrow_token_acc_bump)]
+    pub escrow_token_acc_b: Option<InterfaceAccount<'info, TokenAccount>>,
 
-    #[account(mut, seeds=[b"user_details", taker.key().as_ref()], bump=user_b_details.user_details_bump, close=maker)]
+    #[account(mut, seeds=[b"user_b_details", deal_details.key().as_ref()], bump=user_b_details.user_details_bump, close=maker)]
     pub user_b_details: Account<'info, UserEscrowDetails>,
 
-    system_program: Program<'info, System>,
-}
+    // mints and any of the maker's accounts for them are only needed for an escrow that still holds tokens,
+    // they are swept to the maker
+    #[account(address = user_a_details.mint @ ErrorCode::InvalidMint, mint::token_program = token_program_a)]
+    pub mint_a: Option<InterfaceAccount<'info, Mint>>,
 
-pub fn handler(ctx: Context<Close>) -> Result<()> {
-    // Closing escrow_a escrow_b and controller through CPI because they are token accounts
-    msg!("Deal completion : {:?}", ctx.accounts.deal_details.is_fullfilled);
+    #[account(address = user_b_details.mint @ ErrorCode::InvalidMint, mint::token_program = token_program_b)]
+    pub mint_b: Option<InterfaceAccount<'info, Mint>>,
 
-    let controller_seeds: &[&[&[u8]]] = &[&[b"controller", ctx.accounts.maker.key.as_ref(), &[ctx.accounts.deal_details.escrow_token_controller_bump]]]; 
+    #[account(mut, token::mint = mint_a, token::authority = maker, token::token_program = token_program_a)]
+    pub maker_token_acc_a: Option<InterfaceAccount<'info, TokenAccount>>,
 
-    // Close escrow A
-    close_token_account(
-        &ctx.accounts.escrow_token_acc_a,
-        &ctx.accounts.maker.to_account_info(),
-        &ctx.accounts.escrow_token_controller.to_account_info(),
-        &ctx.accounts.token_program,
-        controller_seeds,
-    )?;
+    #[account(mut, token::mint = mint_b, token::authority = maker, token::token_program = token_program_b)]
+    pub maker_token_acc_b: Option<InterfaceAccount<'info, TokenAccount>>,
 
-    // Close escrow B
-    close_token_account(
-        &ctx.accounts.escrow_token_acc_b,
-        &ctx.accounts.maker.to_account_info(),
-        &ctx.accounts.escrow_token_controller.to_account_info(),
-        &ctx.accounts.token_program,
-        controller_seeds,
-    )?;
+    // only when the deal is listed in the order index of its mint pair
+    #[account(mut)]
+    pub order_index: Option<Account<'info, OrderIndex>>,
 
-    let leftover_funds = ctx.accounts.escrow_token_controller.lamports();
-    if leftover_funds > 0{
+    system_program: Program<'info, System>,
+}
 
-        let cpi_accounts = anchor_lang::system_program::Transfer {
-            from: ctx.accounts.escrow_token_controller.to_account_info(),
-            to: ctx.accounts.maker.to_account_info(),
-        };
-        let cpi_ctx = CpiContext::new_with_signer(
-            ctx.accounts.system_program.to_account_info(),
-            cpi_accounts,
+pub fn handler<'info>(ctx: Context<'_, '_, '_, 'info, Close<'info>>) -> Result<()> {
+    // Closing escrow_a escrow_b and controller through CPI because they are token accounts
+    msg!("Deal state : {:?}", ctx.accounts.deal_details.state);
+    require!(
+        matches!(ctx.accounts.deal_details.state, DealState::Completed | DealState::Expired),
+        ErrorCode::InvalidDealState
+    );
+    unindex_deal(&mut ctx.accounts.deal_details, &mut ctx.accounts.order_index)?;
+
+    let controller_signer = ControllerSeeds::new(&ctx.accounts.deal_details);
+    let controller_seeds: &[&[&[u8]]] = &[&controller_signer.seeds()];
+
+    // every token escrow has to be passed in, whatever is still in it goes to the maker
+    let legs = [
+        (&ctx.accounts.escrow_token_acc_a, &ctx.accounts.user_a_details, &ctx.accounts.mint_a, &ctx.accounts.maker_token_acc_a, &ctx.accounts.token_program_a),
+        (&ctx.accounts.escrow_token_acc_b, &ctx.accounts.user_b_details, &ctx.accounts.mint_b, &ctx.accounts.maker_token_acc_b, &ctx.accounts.token_program_b),
+    ];
+    for (escrow_token_acc, user_details, mint, maker_token_acc, token_program) in legs {
+        if user_details.is_native() {
+            continue;
+        }
+        sweep_escrow(
+            escrow_token_acc.as_ref().ok_or(ErrorCode::MissingTokenAccount)?,
+            mint.as_ref(),
+            maker_token_acc.as_ref(),
+            &ctx.accounts.escrow_token_controller.to_account_info(),
+            token_program,
             controller_seeds,
-        );
-
-        // transfer(tx, leftover_funds)?
-        anchor_lang::system_program::transfer(cpi_ctx, leftover_funds)?;
+            ctx.remaining_accounts,
+        )?;
     }
 
+    close_escrows(
+        [
+            (&ctx.accounts.escrow_token_acc_a, &ctx.accounts.token_program_a),
+            (&ctx.accounts.escrow_token_acc_b, &ctx.accounts.token_program_b),
+        ],
+        &ctx.accounts.maker.to_account_info(),
+        &ctx.accounts.escrow_token_controller.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+        controller_seeds,
+    )?;
 
     Ok(())
 }
-
-fn close_token_account<'info>(
-    token_acc: &InterfaceAccount<'info, TokenAccount>,
-    destination: &AccountInfo<'info>,
-    authority: &AccountInfo<'info>,
-    token_program: &Interface<'info, TokenInterface>,
-    signer_seeds: &[&[&[u8]]],
-) -> Result<()> {
-    let cpi_accounts = CloseAccount {
-        account: token_acc.to_account_info(),
-        destination: destination.to_account_info(),
-        authority: authority.to_account_info(),
-    };
-    let cpi_ctx = CpiContext::new_with_signer(
-        token_program.to_account_info(),
-        cpi_accounts,
-        signer_seeds,
-    );
-    close_account(cpi_ctx)
-}
\ No newline at end of file
@@ -0,0 +1,33 @@
+use anchor_lang::prelude::*;
+
+use crate::{ErrorCode, OfferNonce};
+
+// Returns the rent of a used nonce once its offer has expired, an expired offer can't be taken again without it.
+// Anyone can crank it, the rent always goes to whoever paid it
+#[derive(Accounts)]
+#[instruction(nonce: u64)]
+pub struct CloseOfferNonce<'info> {
+    /// CHECK: just used as a public key wallet to derive the nonce
+    pub maker: AccountInfo<'info>,
+
+    /// CHECK: checked against the nonce, receives its rent
+    #[account(mut, address = offer_nonce.payer @ ErrorCode::InvalidUser)]
+    pub payer: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds=[b"nonce", maker.key().as_ref(), nonce.to_le_bytes().as_ref()],
+        bump=offer_nonce.offer_nonce_bump,
+        close=payer
+    )]
+    pub offer_nonce: Account<'info, OfferNonce>,
+}
+
+pub fn handler(ctx: Context<CloseOfferNonce>, nonce: u64) -> Result<()> {
+    require!(
+        Clock::get()?.unix_timestamp >= ctx.accounts.offer_nonce.expires_at,
+        ErrorCode::DealNotExpired
+    );
+    msg!("Nonce {:?} of {:?} closed", nonce, ctx.accounts.maker.key());
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use anchor_lang::prelude::*;
+
+use crate::allowlist::require_allowed_taker;
+use crate::{DealDetails, DealState, ErrorCode, Proposal};
+
+// Taker proposes different amounts for the deal, calling it again replaces their earlier proposal
+#[derive(Accounts)]
+#[instruction(deal_id: u64)]
+pub struct CounterOffer<'info> {
+    #[account(mut)]
+    pub taker: Signer<'info>,
+
+    /// CHECK: just used as a public key wallet to derive the deal
+    pub maker: AccountInfo<'info>,
+
+    #[account(seeds=[b"deal", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.deal_details_bump)]
+    pub deal_details: Account<'info, DealDetails>,
+
+    #[account(
+        init_if_needed,
+        payer=taker,
+        seeds=[b"proposal", deal_details.key().as_ref(), taker.key().as_ref()],
+        space=8+Proposal::INIT_SPACE,
+        bump
+    )]
+    pub proposal: Account<'info, Proposal>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn handler(ctx: Context<CounterOffer>, new_maker_amt: u64, new_taker_amt: u64, proof: Vec<[u8; 32]>) -> Result<()> {
+    let deal_details = &ctx.accounts.deal_details;
+    if let Some(taker) = deal_details.taker {
+        require_keys_eq!(taker, ctx.accounts.taker.key(), ErrorCode::InvalidUser);
+    }
+    // only takers who could fill the deal get to reprice it
+    require_allowed_taker(&deal_details.taker_allowlist, ctx.accounts.taker.key, &proof)?;
+    require!(
+        Clock::get()?.unix_timestamp < deal_details.expires_at,
+        ErrorCode::DealExpired
+    );
+    // earlier fills were priced on the current terms, they can only change while nobody has filled
+    require!(deal_details.state == DealState::Created, ErrorCode::InvalidDealState);
+    require!(new_maker_amt > 0 && new_taker_amt > 0, ErrorCode::InvalidAmount);
+
+    let proposal = &mut ctx.accounts.proposal;
+    proposal.taker = ctx.accounts.taker.key();
+    proposal.maker_amt = new_maker_amt;
+    proposal.taker_amt = new_taker_amt;
+    proposal.proposal_bump = ctx.bumps.proposal;
+
+    msg!("Counter offer on deal {:?}: {:?} for {:?}", deal_details.deal_id, new_maker_amt, new_taker_amt);
+    Ok(())
+}
@@ -1,7 +1,6 @@
 use anchor_lang::prelude::*;
 use anchor_spl::{associated_token::AssociatedToken, 
         token_interface::{
-        transfer_checked,
         TransferChecked, 
         Mint,
         TokenAccount,
@@ -9,111 +8,196 @@ use anchor_spl::{associated_token::AssociatedToken,
     }
 };
 
-use crate::{DealDetails,UserEscrowDetails};
+use crate::extensions::{gross_amount, require_escrowable_mint, transfer_checked_with_hook};
+use crate::order_index::index_deal;
+use crate::native::{controller_reserve, is_native_mint, transfer_to_controller, wrap_into_escrow};
+use crate::{DealDetails, DealState, ErrorCode, OrderIndex, UserEscrowDetails};
 
 // Instruction to create the deal
 // need to make it more optimized by somehow storing the bumps
 #[derive(Accounts)]
+#[instruction(deal_id: u64)]
 pub struct Create<'info> {
     // to store who made it, the maker will only be able to delete it
     #[account(mut)]
     pub maker : Signer<'info>, 
 
-    /// CHECK: just used as a public key wallet, doesnt need validation
-    pub taker: AccountInfo<'info>,
+    /// CHECK: just used as a public key wallet, doesnt need validation. Left out for an open offer any taker can fill
+    pub taker: Option<AccountInfo<'info>>,
+
+    /// CHECK: just used as a public key wallet. Left out for a deal without an arbiter to settle disputes
+    pub arbiter: Option<AccountInfo<'info>>,
 
     // stores a unique identifier for this specific deal and the amount both users are supposed to pay. Also stores bumps
-    #[account(init, payer=maker, seeds=[b"deal", maker.key().as_ref()], space=8+DealDetails::INIT_SPACE, bump)] // We may need to update the seeds to actually let users make multiple deals
+    // deal_id is part of every seed so a maker can run several deals side by side
+    #[account(init, payer=maker, seeds=[b"deal", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], space=8+DealDetails::INIT_SPACE, bump)]
     pub deal_details : Account<'info, DealDetails>, 
 
-    // mint account for both tokens
-    #[account(mint::token_program = token_program)]
-    pub mint_a: InterfaceAccount<'info, Mint>,
+    // mint account for both tokens, left out for a leg paid in native SOL
+    #[account(mint::token_program = token_program_a)]
+    pub mint_a: Option<InterfaceAccount<'info, Mint>>,
     
-    #[account(mint::token_program = token_program)]
-    pub mint_b: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_program_b)]
+    pub mint_b: Option<InterfaceAccount<'info, Mint>>,
 
-    // token program can only be of token_2022
-    pub token_program : Interface<'info, TokenInterface>,
+    // each leg can sit on SPL Token or Token-2022, every mint is checked against the program of its own leg
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
     pub associated_token_program: Program<'info, AssociatedToken>,
 
-    // existing user token account which they are transferring the fund from
+    // existing user token account which they are transferring the fund from, not needed for native SOL or wSOL
     #[account(
         init_if_needed,
         payer=maker,
         associated_token::mint = mint_a,
         associated_token::authority = maker,
-        associated_token::token_program = token_program
+        associated_token::token_program = token_program_a
     )]
-    pub user_token_acc_a: InterfaceAccount<'info, TokenAccount>,
+    pub user_token_acc_a: Option<InterfaceAccount<'info, TokenAccount>>,
 
-    #[account(seeds=[b"controller", maker.key().as_ref()], bump) ]
+    // also holds the lamports of a native SOL leg
+    #[account(mut, seeds=[b"controller", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump) ]
     pub escrow_token_controller : SystemAccount<'info>,
 
     // account program will create to store users token temporarily
+    // keyed under the deal PDA so deals never share an escrow account, even with the same taker
     #[account(
         init_if_needed,
         payer=maker, 
-        seeds=[b"token", maker.key().as_ref()],
+        seeds=[b"token_a", deal_details.key().as_ref()],
         token::mint = mint_a,
         token::authority = escrow_token_controller,
-        token::token_program = token_program, 
+        token::token_program = token_program_a, 
         bump)]
-    pub escrow_token_acc_a: InterfaceAccount<'info, TokenAccount>,
+    pub escrow_token_acc_a: Option<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(
         init_if_needed,
         payer=maker, 
-        seeds=[b"token", taker.key().as_ref()],
+        seeds=[b"token_b", deal_details.key().as_ref()],
         token::mint = mint_b,
         token::authority = escrow_token_controller,
-        token::token_program = token_program,
+        token::token_program = token_program_b,
         bump)]
-    pub escrow_token_acc_b: InterfaceAccount<'info, TokenAccount>,
+    pub escrow_token_acc_b: Option<InterfaceAccount<'info, TokenAccount>>,
 
-    #[account(init, payer=maker, seeds=[b"user_details", maker.key().as_ref() ], bump, space=8+UserEscrowDetails::INIT_SPACE)]
+    #[account(init, payer=maker, seeds=[b"user_a_details", deal_details.key().as_ref()], bump, space=8+UserEscrowDetails::INIT_SPACE)]
     pub user_a_details : Account<'info, UserEscrowDetails>,
 
-    #[account(init, payer=maker, seeds=[b"user_details", taker.key().as_ref() ], bump, space=8+UserEscrowDetails::INIT_SPACE)]
+    #[account(init, payer=maker, seeds=[b"user_b_details", deal_details.key().as_ref()], bump, space=8+UserEscrowDetails::INIT_SPACE)]
     pub user_b_details : Account<'info, UserEscrowDetails>,
+
+    // lists the deal in the order index of its mint pair, left out to keep the deal unlisted
+    #[account(mut)]
+    pub order_index: Option<Account<'info, OrderIndex>>,
 }
 
-pub fn handler(ctx: Context<Create>, maker_amt : u64, taker_amt: u64) -> Result<()> {
+pub fn handler<'info>(ctx: Context<'_, '_, '_, 'info, Create<'info>>, deal_id: u64, maker_amt : u64, taker_amt: u64, expires_at: i64, bind_first_taker: bool) -> Result<()> {
+    require!(expires_at > Clock::get()?.unix_timestamp, ErrorCode::InvalidExpiry);
+    require!(maker_amt > 0 && taker_amt > 0, ErrorCode::InvalidAmount);
+
+    // a leg without a mint is paid in native SOL, recorded as Pubkey::default()
+    let mint_a_key = ctx.accounts.mint_a.as_ref().map(|mint| mint.key()).unwrap_or_default();
+    let mint_b_key = ctx.accounts.mint_b.as_ref().map(|mint| mint.key()).unwrap_or_default();
+    require_keys_neq!(mint_a_key, mint_b_key, ErrorCode::IdenticalMints);
+    for mint in [&ctx.accounts.mint_a, &ctx.accounts.mint_b].into_iter().flatten() {
+        require_escrowable_mint(mint)?;
+    }
+    if ctx.accounts.mint_b.is_some() {
+        require!(ctx.accounts.escrow_token_acc_b.is_some(), ErrorCode::MissingTokenAccount);
+    }
+    // a deal with a fixed taker is bound from the start
+    require!(!bind_first_taker || ctx.accounts.taker.is_none(), ErrorCode::BindingRequiresOpenOffer);
+    // a dispute is always between the maker and one known taker
+    if let Some(arbiter) = &ctx.accounts.arbiter {
+        let taker = ctx.accounts.taker.as_ref().ok_or(ErrorCode::InvalidArbiter)?;
+        require!(
+            arbiter.key() != ctx.accounts.maker.key() && arbiter.key() != taker.key(),
+            ErrorCode::InvalidArbiter
+        );
+    }
+
     // Store passed accounts into user_a_details and user_b_details accordingly
+    ctx.accounts.deal_details.deal_id = deal_id;
     ctx.accounts.deal_details.deal_details_bump = ctx.bumps.deal_details;
     ctx.accounts.deal_details.escrow_token_controller_bump = ctx.bumps.escrow_token_controller;
     ctx.accounts.deal_details.maker = ctx.accounts.maker.key();
-    ctx.accounts.deal_details.taker = ctx.accounts.taker.key();
-    ctx.accounts.deal_details.is_fullfilled = false;
+    ctx.accounts.deal_details.taker = ctx.accounts.taker.as_ref().map(|taker| taker.key());
+    ctx.accounts.deal_details.state = DealState::Created;
+    ctx.accounts.deal_details.maker_claimed = false;
+    ctx.accounts.deal_details.taker_claimed = false;
+    ctx.accounts.deal_details.expires_at = expires_at;
+    ctx.accounts.deal_details.maker_amt_remaining = maker_amt;
+    ctx.accounts.deal_details.taker_amt_remaining = taker_amt;
+    ctx.accounts.deal_details.maker_amt_owed = 0;
+    ctx.accounts.deal_details.basket_legs = 0;
+    ctx.accounts.deal_details.arbiter = ctx.accounts.arbiter.as_ref().map(|arbiter| arbiter.key());
+    ctx.accounts.deal_details.dispute_open = false;
+    ctx.accounts.deal_details.price_condition = None;
+    ctx.accounts.deal_details.taker_allowlist = None;
+    ctx.accounts.deal_details.index_page = None;
+    ctx.accounts.deal_details.bind_first_taker = bind_first_taker;
+    if let Some(order_index) = &mut ctx.accounts.order_index {
+        index_deal(&mut ctx.accounts.deal_details, order_index, &mint_a_key, &mint_b_key)?;
+    }
 
     // set maker details
     ctx.accounts.user_a_details.mint_amt = maker_amt;
-    ctx.accounts.user_a_details.mint = ctx.accounts.mint_a.key();
+    ctx.accounts.user_a_details.mint = mint_a_key;
     // ctx.accounts.user_a_details.user_token_acc = ctx.accounts.user_token_acc_a.key();
     // ctx.accounts.user_a_details.escrow_token_acc = ctx.accounts.escrow_token_acc_a.key();
-    ctx.accounts.user_a_details.escrow_token_acc_bump = ctx.bumps.escrow_token_acc_a;
+    ctx.accounts.user_a_details.escrow_token_acc_bump = ctx.bumps.escrow_token_acc_a.unwrap_or_default();
     ctx.accounts.user_a_details.user_details_bump = ctx.bumps.user_a_details;
 
     // set taker details
     ctx.accounts.user_b_details.mint_amt = taker_amt;
-    ctx.accounts.user_b_details.mint = ctx.accounts.mint_b.key();
+    ctx.accounts.user_b_details.mint = mint_b_key;
     // ctx.accounts.user_b_details.user_token_acc = ctx.accounts.user_token_acc_b.key();
     // ctx.accounts.user_b_details.escrow_token_acc = ctx.accounts.escrow_token_acc_b.key();
-    ctx.accounts.user_b_details.escrow_token_acc_bump = ctx.bumps.escrow_token_acc_b;
+    ctx.accounts.user_b_details.escrow_token_acc_bump = ctx.bumps.escrow_token_acc_b.unwrap_or_default();
     ctx.accounts.user_b_details.user_details_bump = ctx.bumps.user_b_details;
 
-    // Deposit mint_a from user_token_acc_a to escrow_token_acc_a
-    let cpi_accounts = TransferChecked {
-        mint: ctx.accounts.mint_a.to_account_info(),
-        from: ctx.accounts.user_token_acc_a.to_account_info(),
-        to: ctx.accounts.escrow_token_acc_a.to_account_info(),
-        authority: ctx.accounts.maker.to_account_info(),
-    };
-    let cpi_program = ctx.accounts.token_program.to_account_info();
-    let cpi_context = CpiContext::new(cpi_program, cpi_accounts);
-
-    transfer_checked(cpi_context, maker_amt, ctx.accounts.mint_a.decimals)?;
+    // the controller holds native SOL on top of its rent exempt reserve, so it never drops below what the runtime allows
+    if ctx.accounts.mint_a.is_none() || ctx.accounts.mint_b.is_none() {
+        transfer_to_controller(
+            &ctx.accounts.maker.to_account_info(),
+            &ctx.accounts.escrow_token_controller.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            controller_reserve()?,
+        )?;
+    }
+
+    match &ctx.accounts.mint_a {
+        // wSOL is wrapped from the maker's lamports, no wSOL account needed on their side
+        Some(mint_a) if is_native_mint(&mint_a.key()) => wrap_into_escrow(
+            &ctx.accounts.maker.to_account_info(),
+            ctx.accounts.escrow_token_acc_a.as_ref().ok_or(ErrorCode::MissingTokenAccount)?,
+            &ctx.accounts.token_program_a,
+            &ctx.accounts.system_program.to_account_info(),
+            maker_amt,
+        )?,
+        // Deposit mint_a from user_token_acc_a to escrow_token_acc_a
+        Some(mint_a) => {
+            let cpi_accounts = TransferChecked {
+                mint: mint_a.to_account_info(),
+                from: ctx.accounts.user_token_acc_a.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+                to: ctx.accounts.escrow_token_acc_a.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+                authority: ctx.accounts.maker.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program_a.to_account_info();
+            let cpi_context = CpiContext::new(cpi_program, cpi_accounts).with_remaining_accounts(ctx.remaining_accounts.to_vec());
+
+            // with a transfer fee mint the maker covers the fee, so the escrow holds exactly maker_amt
+            transfer_checked_with_hook(cpi_context, gross_amount(mint_a.as_ref(), maker_amt)?, mint_a.decimals)?;
+        }
+        None => transfer_to_controller(
+            &ctx.accounts.maker.to_account_info(),
+            &ctx.accounts.escrow_token_controller.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            maker_amt,
+        )?,
+    }
 
     msg!("Escrow deal created {:?}", ctx.program_id);
     msg!("Deal created by {:?}", ctx.accounts.maker);
@@ -1,11 +1,18 @@
 use anchor_lang::prelude::*;
 use anchor_spl::token_interface::{
-    transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked,
+    Mint, TokenAccount, TokenInterface, TransferChecked,
 };
 
-use crate::{DealDetails, UserEscrowDetails};
+use crate::basket::{basket_legs, fund_leg, hook_accounts, leg_accounts};
+use crate::allowlist::require_allowed_taker;
+use crate::oracle::require_price_condition;
+use crate::extensions::{gross_amount, transfer_checked_with_hook};
+use crate::native::{is_native_mint, transfer_to_controller, wrap_into_escrow};
+use crate::order_index::unindex_deal;
+use crate::{BasketDetails, DealDetails, DealState, ErrorCode, FillDetails, LegSide, OrderIndex, UserEscrowDetails};
 
 #[derive(Accounts)]
+#[instruction(deal_id: u64)]
 pub struct Deposit<'info> {
     // #[account(mut)]
     // pub signer: Signer<'info>,
@@ -16,66 +23,189 @@ pub struct Deposit<'info> {
     #[account(mut)]
     pub taker: Signer<'info>,
 
-    #[account(mut, seeds=[b"deal", maker.key().as_ref()], bump=deal_details.deal_details_bump)]
+    #[account(mut, seeds=[b"deal", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.deal_details_bump)]
     pub deal_details: Account<'info, DealDetails>,
 
     #[account(mut,
         associated_token::mint = user_b_details.mint,
         associated_token::authority = taker,
-        associated_token::token_program = token_program
+        associated_token::token_program = token_program_b
     )]
-    pub user_token_acc_b: InterfaceAccount<'info, TokenAccount>,
+    pub user_token_acc_b: Option<InterfaceAccount<'info, TokenAccount>>,
 
-    #[account(mint::token_program=token_program)]
-    pub mint: InterfaceAccount<'info, Mint>,
+    // token accounts and mint are left out when the taker side is paid in native SOL,
+    // a wSOL taker side only needs the mint as it is wrapped from the taker's lamports
+    #[account(address = user_b_details.mint @ ErrorCode::InvalidMint, mint::token_program=token_program_b)]
+    pub mint: Option<InterfaceAccount<'info, Mint>>,
 
-    pub token_program: Interface<'info, TokenInterface>,
+    // program of the taker side, either SPL Token or Token-2022
+    pub token_program_b: Interface<'info, TokenInterface>,
 
     // need to structure deal_details better to identify whose bump this is
-    #[account(seeds=[b"token", maker.key().as_ref()], bump=user_a_details.escrow_token_acc_bump)]
-    pub escrow_token_acc_a: InterfaceAccount<'info, TokenAccount>,
+    #[account(seeds=[b"token_a", deal_details.key().as_ref()], bump=user_a_details.escrow_token_acc_bump)]
+    pub escrow_token_acc_a: Option<InterfaceAccount<'info, TokenAccount>>,
 
-    #[account(seeds=[b"user_details", maker.key().as_ref()], bump=user_a_details.user_details_bump)]
+    #[account(seeds=[b"user_a_details", deal_details.key().as_ref()], bump=user_a_details.user_details_bump)]
     pub user_a_details: Account<'info, UserEscrowDetails>,
 
-    #[account(mut, seeds=[b"token", taker.key().as_ref()], bump=user_b_details.escrow_token_acc_bump)]
-    pub escrow_token_acc_b: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds=[b"token_b", deal_details.key().as_ref()],
+        bump=user_b_details.escrow_token_acc_bump,
+        token::mint=mint,
+        token::token_program=token_program_b
+    )]
+    pub escrow_token_acc_b: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // receives the lamports of a native SOL leg
+    #[account(mut, seeds=[b"controller", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.escrow_token_controller_bump)]
+    pub escrow_token_controller: SystemAccount<'info>,
 
-    #[account(seeds=[b"user_details", taker.key().as_ref()], bump=user_b_details.user_details_bump)]
+    #[account(seeds=[b"user_b_details", deal_details.key().as_ref()], bump=user_b_details.user_details_bump)]
     pub user_b_details: Account<'info, UserEscrowDetails>,
+
+    // tracks the share of mint_a this taker is owed, a taker can fill the same deal more than once
+    #[account(
+        init_if_needed,
+        payer=taker,
+        seeds=[b"fill", deal_details.key().as_ref(), taker.key().as_ref()],
+        space=8+FillDetails::INIT_SPACE,
+        bump
+    )]
+    pub fill_details: Account<'info, FillDetails>,
+
+    // only for a basket deal, the taker legs are paid in from the accounts passed in remaining_accounts
+    pub basket_details: Option<Account<'info, BasketDetails>>,
+
+    /// CHECK: only for a price conditional deal, checked against the oracle in its condition
+    pub price_oracle: Option<UncheckedAccount<'info>>,
+
+    // only when the deal is listed in the order index of its mint pair, it comes off once the last fill lands
+    #[account(mut)]
+    pub order_index: Option<Account<'info, OrderIndex>>,
+
+    pub system_program: Program<'info, System>,
 }
 
-pub fn handler(ctx: Context<Deposit>, amount: u64) -> Result<()> {
+pub fn handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, Deposit<'info>>,
+    amount: u64,
+    expected_maker_amt: u64,
+    max_taker_amt: u64,
+    proof: Vec<[u8; 32]>,
+) -> Result<()> {
     msg!("Deposit initiating of amount: {:?}", amount);
 
-    // Check if signer has already submitted required tokens
-    // let deal_details = ctx.accounts.deal_details.to_account_info();
-    // if deal_details.
+    // open offers stay open to every taker until the whole amount is filled, unless bound to the first one
+    if let Some(taker) = ctx.accounts.deal_details.taker {
+        require_keys_eq!(taker, ctx.accounts.taker.key(), ErrorCode::InvalidUser);
+    }
+    // proof is only read for a Merkle root allowlist, pass it empty otherwise
+    require_allowed_taker(&ctx.accounts.deal_details.taker_allowlist, ctx.accounts.taker.key, &proof)?;
+
+    // the fill is priced on the terms the taker saw, not on whatever the deal holds by the time it lands
+    require!(
+        ctx.accounts.user_a_details.mint_amt == expected_maker_amt && ctx.accounts.user_b_details.mint_amt <= max_taker_amt,
+        ErrorCode::TermsChanged
+    );
+
+    let deal_details = &ctx.accounts.deal_details;
+    require!(!deal_details.dispute_open, ErrorCode::DisputeOpen);
+    require!(!deal_details.state.is_filled(), ErrorCode::DealAlreadyFulfilled);
+    require!(
+        matches!(deal_details.state, DealState::Created | DealState::PartiallyFilled),
+        ErrorCode::InvalidDealState
+    );
+    // checked after the state, an expired deal that was already cranked reports its state
+    require!(Clock::get()?.unix_timestamp < deal_details.expires_at, ErrorCode::DealExpired);
+    require!(amount > 0 && amount <= deal_details.taker_amt_remaining, ErrorCode::InvalidFillAmount);
+    require_price_condition(
+        &deal_details.price_condition,
+        ctx.accounts.price_oracle.as_ref().map(|oracle| oracle.as_ref()),
+    )?;
+
+    // every leg of a basket is funded in this one instruction, so the deal is never settled on part of it
+    let legs = basket_legs(deal_details, &ctx.accounts.basket_details)?;
+    require!(legs.is_empty() || amount == deal_details.taker_amt_remaining, ErrorCode::BasketRequiresFullFill);
+    require!(
+        ctx.accounts.user_a_details.milestones.is_empty() || amount == deal_details.taker_amt_remaining,
+        ErrorCode::MilestonesRequireFullFill
+    );
+    let hook_accounts = hook_accounts(ctx.remaining_accounts, legs);
+
+    // Pro-rata share of mint_a, the last fill takes whatever is left so rounding never strands tokens in escrow
+    let maker_share = if amount == deal_details.taker_amt_remaining {
+        deal_details.maker_amt_remaining
+    } else {
+        (amount as u128 * ctx.accounts.user_a_details.mint_amt as u128 / ctx.accounts.user_b_details.mint_amt as u128) as u64
+    };
+    require!(maker_share > 0, ErrorCode::InvalidFillAmount);
+
+    if ctx.accounts.user_b_details.is_native() {
+        transfer_to_controller(
+            &ctx.accounts.taker.to_account_info(),
+            &ctx.accounts.escrow_token_controller.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            amount,
+        )?;
+    } else if is_native_mint(&ctx.accounts.user_b_details.mint) {
+        // wSOL is wrapped from the taker's lamports, no wSOL account needed on their side
+        wrap_into_escrow(
+            &ctx.accounts.taker.to_account_info(),
+            ctx.accounts.escrow_token_acc_b.as_ref().ok_or(ErrorCode::MissingTokenAccount)?,
+            &ctx.accounts.token_program_b,
+            &ctx.accounts.system_program.to_account_info(),
+            amount,
+        )?;
+    } else {
+        let mint = ctx.accounts.mint.as_ref().ok_or(ErrorCode::MissingTokenAccount)?;
+        let decimals = mint.decimals;
+
+        let cpi_accounts = TransferChecked {
+            mint: mint.to_account_info(),
+            from: ctx.accounts.user_token_acc_b.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+            to: ctx.accounts.escrow_token_acc_b.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+            authority: ctx.accounts.taker.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program_b.to_account_info();
+        let cpi_context = CpiContext::new(cpi_program, cpi_accounts).with_remaining_accounts(hook_accounts.to_vec());
+
+        // the taker covers any transfer fee, the escrow is credited the full amount the fill is priced on
+        transfer_checked_with_hook(cpi_context, gross_amount(mint.as_ref(), amount)?, decimals)?;
+    }
 
-    let decimals = ctx.accounts.mint.decimals;
+    let deal_key = ctx.accounts.deal_details.key();
+    for (index, leg) in legs.iter().enumerate().filter(|(_, leg)| leg.side == LegSide::Taker) {
+        let leg_accounts = leg_accounts(ctx.remaining_accounts, &deal_key, index, leg)?;
+        fund_leg(&leg_accounts, leg, &ctx.accounts.taker.to_account_info(), hook_accounts)?;
+    }
 
-    let cpi_accounts = TransferChecked {
-        mint: ctx.accounts.mint.to_account_info(),
-        from: ctx.accounts.user_token_acc_b.to_account_info(),
-        to: ctx.accounts.escrow_token_acc_b.to_account_info(),
-        authority: ctx.accounts.taker.to_account_info(),
+    // The taker can withdraw their share straight away, no need to wait for the rest of the deal
+    let fill_details = &mut ctx.accounts.fill_details;
+    fill_details.taker = ctx.accounts.taker.key();
+    fill_details.maker_amt_owed += maker_share;
+    fill_details.fill_details_bump = ctx.bumps.fill_details;
+
+    let deal_details = &mut ctx.accounts.deal_details;
+    deal_details.taker_amt_remaining -= amount;
+    deal_details.maker_amt_remaining -= maker_share;
+    deal_details.maker_amt_owed += maker_share;
+
+    // Check if the taker side has been filled completely
+    deal_details.state = if deal_details.taker_amt_remaining == 0 {
+        DealState::TakerDeposited
+    } else {
+        DealState::PartiallyFilled
     };
-    let cpi_program = ctx.accounts.token_program.to_account_info();
-    let cpi_context = CpiContext::new(cpi_program, cpi_accounts);
-
-    transfer_checked(cpi_context, amount, decimals)?;
-
-    // After sending token we update the current value
-    ctx.accounts.escrow_token_acc_b.reload()?;
-
-    // Probably very risky here to check user_b_details.mint_amt, need to add better seeds to make it secure. Atm im just learning, we can add ids to make seeds secure
-    // Check if both sides has transfered their amount of tokens, if yes transfer
-    if ctx.accounts.user_a_details.mint_amt <= ctx.accounts.escrow_token_acc_a.amount
-        && ctx.accounts.user_b_details.mint_amt <= ctx.accounts.escrow_token_acc_b.amount
-    {
-        msg!("WE ARE RUNNING");
-        // let the users withdraw
-        ctx.accounts.deal_details.is_fullfilled = true;
+    // a bound offer is no longer open to anyone else, so it comes off the index as well
+    if deal_details.bind_first_taker && deal_details.taker.is_none() {
+        deal_details.taker = Some(ctx.accounts.taker.key());
+        unindex_deal(&mut ctx.accounts.deal_details, &mut ctx.accounts.order_index)?;
+    }
+    if ctx.accounts.deal_details.state == DealState::TakerDeposited {
+        unindex_deal(&mut ctx.accounts.deal_details, &mut ctx.accounts.order_index)?;
     }
+
+    msg!("Filled {:?}, maker share {:?}, left to fill {:?}", amount, maker_share, ctx.accounts.deal_details.taker_amt_remaining);
     Ok(())
 }
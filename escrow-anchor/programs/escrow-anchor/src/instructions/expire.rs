@@ -0,0 +1,166 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+
+use crate::basket::{basket_legs, hook_accounts, refund_basket};
+use crate::escrow_accounts::{close_escrows, refund_maker_leg_a, ControllerSeeds, MakerRefund};
+use crate::native::native_escrow_amount;
+use crate::order_index::unindex_deal;
+use crate::{BasketDetails, DealDetails, DealState, ErrorCode, OrderIndex, UserEscrowDetails};
+
+// Permissionless crank, once a deal is past its expiry anyone can refund the unfilled part to the maker.
+// The deal accounts are closed as well unless takers of a partial fill still have to withdraw
+// or the maker has not collected their mint_b yet, in which case close does it afterwards
+#[derive(Accounts)]
+#[instruction(deal_id: u64)]
+pub struct Expire<'info> {
+    // whoever cranks the expiry, does not need to be part of the deal. Fronts the rent of the account a wSOL refund
+    // is unwrapped through, it comes straight back
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    /// CHECK: just used as a public key wallet, receives the refund and the rent of the closed accounts
+    #[account(mut)]
+    pub maker: AccountInfo<'info>,
+
+    #[account(mut, seeds=[b"deal", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.deal_details_bump)]
+    pub deal_details: Account<'info, DealDetails>,
+
+    #[account(mut, seeds=[b"controller", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.escrow_token_controller_bump)]
+    pub escrow_token_controller: SystemAccount<'info>,
+
+    // mint and token accounts of a native SOL leg are left out, the refund is paid in lamports
+    #[account(address = user_a_details.mint @ ErrorCode::InvalidMint, mint::token_program = token_program_a)]
+    pub mint_a: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut, seeds=[b"user_a_details", deal_details.key().as_ref()], bump=user_a_details.user_details_bump)]
+    pub user_a_details: Account<'info, UserEscrowDetails>,
+
+    #[account(mut, seeds=[b"user_b_details", deal_details.key().as_ref()], bump=user_b_details.user_details_bump)]
+    pub user_b_details: Account<'info, UserEscrowDetails>,
+
+    #[account(mut, seeds=[b"token_a", deal_details.key().as_ref()], bump=user_a_details.escrow_token_acc_bump)]
+    pub escrow_token_acc_a: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, seeds=[b"token_b", deal_details.key().as_ref()], bump=user_b_details.escrow_token_acc_bump)]
+    pub escrow_token_acc_b: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // maker's account the deposit was originally made from, left out for wSOL which is refunded unwrapped
+    #[account(
+        mut,
+        associated_token::mint = mint_a,
+        associated_token::authority = maker,
+        associated_token::token_program = token_program_a
+    )]
+    pub maker_token_acc_a: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: created and closed again within the instruction to unwrap a wSOL refund, only needed for a wSOL maker side
+    #[account(mut, seeds=[b"unwrap", deal_details.key().as_ref(), signer.key().as_ref()], bump)]
+    pub unwrap_token_acc: Option<UncheckedAccount<'info>>,
+
+    // only for a basket deal, every leg is refunded to the maker's accounts passed in remaining_accounts
+    #[account(mut)]
+    pub basket_details: Option<Account<'info, BasketDetails>>,
+
+    // only when the deal is listed in the order index of its mint pair
+    #[account(mut)]
+    pub order_index: Option<Account<'info, OrderIndex>>,
+
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+pub fn handler<'info>(ctx: Context<'_, '_, '_, 'info, Expire<'info>>) -> Result<()> {
+    require!(
+        Clock::get()?.unix_timestamp >= ctx.accounts.deal_details.expires_at,
+        ErrorCode::DealNotExpired
+    );
+
+    // a fulfilled deal no longer locks anything, both parties can withdraw what they are owed.
+    // Whatever takers paid into escrow_token_acc_b on partial fills is already the maker's to withdraw
+    let state = ctx.accounts.deal_details.state;
+    require!(!ctx.accounts.deal_details.dispute_open, ErrorCode::DisputeOpen);
+    require!(!state.is_filled(), ErrorCode::DealAlreadyFulfilled);
+    // cranking an expired deal again closes it once the remaining takers have withdrawn
+    require!(
+        matches!(state, DealState::Created | DealState::PartiallyFilled | DealState::Expired),
+        ErrorCode::InvalidDealState
+    );
+
+    let controller_signer = ControllerSeeds::new(&ctx.accounts.deal_details);
+    let controller_seeds: &[&[&[u8]]] = &[&controller_signer.seeds()];
+
+    // a basket deal cannot be partially filled, so nothing in its legs is owed to a taker
+    let legs = basket_legs(&ctx.accounts.deal_details, &ctx.accounts.basket_details)?.to_vec();
+    let hook_accounts = hook_accounts(ctx.remaining_accounts, &legs);
+
+    // only the unfilled part goes back, the rest of escrow_token_acc_a is owed to takers
+    let refund_amount = ctx.accounts.deal_details.maker_amt_remaining;
+    if refund_amount > 0 {
+        let maker_refund = MakerRefund {
+            maker: ctx.accounts.maker.clone(),
+            payer: ctx.accounts.signer.to_account_info(),
+            deal: ctx.accounts.deal_details.key(),
+            mint_a: &ctx.accounts.mint_a,
+            user_a_details: &ctx.accounts.user_a_details,
+            escrow_token_acc_a: &ctx.accounts.escrow_token_acc_a,
+            maker_token_acc_a: &ctx.accounts.maker_token_acc_a,
+            unwrap_token_acc: ctx.accounts.unwrap_token_acc.as_ref().map(|account| account.to_account_info()),
+            unwrap_bump: ctx.bumps.unwrap_token_acc,
+            controller: ctx.accounts.escrow_token_controller.to_account_info(),
+            token_program_a: &ctx.accounts.token_program_a,
+            system_program: ctx.accounts.system_program.to_account_info(),
+        };
+        refund_maker_leg_a(&maker_refund, controller_seeds, hook_accounts, refund_amount)?;
+    }
+
+    if let Some(basket_details) = &ctx.accounts.basket_details {
+        refund_basket(
+            ctx.remaining_accounts,
+            &ctx.accounts.deal_details.key(),
+            &legs,
+            &ctx.accounts.maker,
+            &ctx.accounts.escrow_token_controller.to_account_info(),
+            controller_seeds,
+        )?;
+        basket_details.close(ctx.accounts.maker.to_account_info())?;
+        ctx.accounts.deal_details.basket_legs = 0;
+    }
+
+    unindex_deal(&mut ctx.accounts.deal_details, &mut ctx.accounts.order_index)?;
+    ctx.accounts.deal_details.maker_amt_remaining = 0;
+    ctx.accounts.deal_details.state = DealState::Expired;
+
+    // a native SOL leg counts as empty once the controller holds nothing above its reserve
+    let escrow_a_empty = match &mut ctx.accounts.escrow_token_acc_a {
+        Some(escrow_token_acc_a) => {
+            escrow_token_acc_a.reload()?;
+            escrow_token_acc_a.amount == 0
+        }
+        None => ctx.accounts.user_a_details.is_native() && native_escrow_amount(&ctx.accounts.escrow_token_controller)? == 0,
+    };
+    let escrow_b_empty = match &ctx.accounts.escrow_token_acc_b {
+        Some(escrow_token_acc_b) => escrow_token_acc_b.amount == 0,
+        None => ctx.accounts.user_b_details.is_native() && native_escrow_amount(&ctx.accounts.escrow_token_controller)? == 0,
+    };
+
+    if escrow_a_empty && escrow_b_empty {
+        close_escrows(
+            [
+                (&ctx.accounts.escrow_token_acc_a, &ctx.accounts.token_program_a),
+                (&ctx.accounts.escrow_token_acc_b, &ctx.accounts.token_program_b),
+            ],
+            &ctx.accounts.maker,
+            &ctx.accounts.escrow_token_controller.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            controller_seeds,
+        )?;
+
+        ctx.accounts.user_a_details.close(ctx.accounts.maker.to_account_info())?;
+        ctx.accounts.user_b_details.close(ctx.accounts.maker.to_account_info())?;
+        ctx.accounts.deal_details.close(ctx.accounts.maker.to_account_info())?;
+    }
+
+    msg!("Deal {:?} expired, refunded {:?} to maker", ctx.accounts.deal_details.deal_id, refund_amount);
+    Ok(())
+}
@@ -0,0 +1,32 @@
+use anchor_lang::prelude::*;
+
+use crate::OrderIndex;
+
+// Creates a page of the order index of a mint pair, anyone can pay for it. Pass Pubkey::default() for a native SOL leg
+#[derive(Accounts)]
+#[instruction(mint_a: Pubkey, mint_b: Pubkey, page: u32)]
+pub struct InitOrderIndex<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        init,
+        payer=payer,
+        seeds=[b"index", mint_a.as_ref(), mint_b.as_ref(), page.to_le_bytes().as_ref()],
+        space=8+OrderIndex::INIT_SPACE,
+        bump
+    )]
+    pub order_index: Account<'info, OrderIndex>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn handler(ctx: Context<InitOrderIndex>, mint_a: Pubkey, mint_b: Pubkey, page: u32) -> Result<()> {
+    let order_index = &mut ctx.accounts.order_index;
+    order_index.mint_a = mint_a;
+    order_index.mint_b = mint_b;
+    order_index.page = page;
+    order_index.order_index_bump = ctx.bumps.order_index;
+    msg!("Order index page {:?} created for {:?} / {:?}", page, mint_a, mint_b);
+    Ok(())
+}
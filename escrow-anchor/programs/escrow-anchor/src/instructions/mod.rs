@@ -4,10 +4,44 @@ pub mod deposit;
 pub mod withdraw;
 pub mod check;
 pub mod close;
+pub mod take;
+pub mod cancel;
+pub mod expire;
+pub mod add_leg;
+pub mod raise_dispute;
+pub mod resolve_dispute;
+pub mod set_milestones;
+pub mod approve_milestone;
+pub mod set_price_condition;
+pub mod counter_offer;
+pub mod accept_counter;
+pub mod reject_counter;
+pub mod set_taker_allowlist;
+pub mod take_signed_offer;
+pub mod cancel_signed_offer;
+pub mod close_offer_nonce;
+pub mod init_order_index;
 
 pub use initialize::*;
 pub use create::*;
 pub use deposit::*;
 pub use withdraw::*;
 pub use check::*;
-pub use close::*;
\ No newline at end of file
+pub use close::*;
+pub use take::*;
+pub use cancel::*;
+pub use expire::*;
+pub use add_leg::*;
+pub use raise_dispute::*;
+pub use resolve_dispute::*;
+pub use set_milestones::*;
+pub use approve_milestone::*;
+pub use set_price_condition::*;
+pub use counter_offer::*;
+pub use accept_counter::*;
+pub use reject_counter::*;
+pub use set_taker_allowlist::*;
+pub use take_signed_offer::*;
+pub use cancel_signed_offer::*;
+pub use close_offer_nonce::*;
+pub use init_order_index::*;
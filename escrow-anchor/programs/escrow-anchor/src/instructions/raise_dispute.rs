@@ -0,0 +1,39 @@
+use anchor_lang::prelude::*;
+
+use crate::{DealDetails, DealState, ErrorCode};
+
+// Either party of a deal with an arbiter can freeze it, nothing moves until the arbiter resolves the dispute
+#[derive(Accounts)]
+#[instruction(deal_id: u64)]
+pub struct RaiseDispute<'info> {
+    pub signer: Signer<'info>,
+
+    /// CHECK: just used as a public key wallet to derive the deal
+    pub maker: AccountInfo<'info>,
+
+    #[account(mut, seeds=[b"deal", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.deal_details_bump)]
+    pub deal_details: Account<'info, DealDetails>,
+}
+
+pub fn handler(ctx: Context<RaiseDispute>) -> Result<()> {
+    let deal_details = &mut ctx.accounts.deal_details;
+    require!(deal_details.arbiter.is_some(), ErrorCode::NoArbiter);
+    require!(!deal_details.dispute_open, ErrorCode::DisputeOpen);
+
+    let signer = ctx.accounts.signer.key();
+    require!(
+        signer == deal_details.maker || Some(signer) == deal_details.taker,
+        ErrorCode::InvalidUser
+    );
+
+    // both sides must still have something in escrow, before the taker pays in the maker can simply cancel
+    require!(
+        matches!(deal_details.state, DealState::PartiallyFilled | DealState::TakerDeposited),
+        ErrorCode::InvalidDealState
+    );
+    require!(deal_details.basket_legs == 0, ErrorCode::BasketNotSupported);
+
+    deal_details.dispute_open = true;
+    msg!("Dispute raised on deal {:?} by {:?}", deal_details.deal_id, signer);
+    Ok(())
+}
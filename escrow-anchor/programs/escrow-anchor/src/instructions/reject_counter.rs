@@ -0,0 +1,42 @@
+use anchor_lang::prelude::*;
+
+use crate::{ErrorCode, Proposal};
+
+// Maker turns a counter offer down, the taker can also withdraw their own. Either way the taker gets the rent back.
+// Works after the deal was closed as well, a proposal nobody answered would otherwise keep its rent for good
+#[derive(Accounts)]
+#[instruction(deal_id: u64)]
+pub struct RejectCounter<'info> {
+    // the maker or the taker who made the proposal
+    pub signer: Signer<'info>,
+
+    /// CHECK: just used as a public key wallet to derive the deal
+    pub maker: AccountInfo<'info>,
+
+    /// CHECK: checked against the proposal, receives its rent
+    #[account(mut, address = proposal.taker @ ErrorCode::InvalidUser)]
+    pub taker: AccountInfo<'info>,
+
+    /// CHECK: only its address is used, the deal may already be closed
+    #[account(seeds=[b"deal", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump)]
+    pub deal_details: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds=[b"proposal", deal_details.key().as_ref(), taker.key().as_ref()],
+        bump=proposal.proposal_bump,
+        close=taker
+    )]
+    pub proposal: Account<'info, Proposal>,
+}
+
+pub fn handler(ctx: Context<RejectCounter>, deal_id: u64) -> Result<()> {
+    let signer = ctx.accounts.signer.key();
+    // the deal is derived from the maker, so this is the deal's maker whether or not the deal is still around
+    require!(
+        signer == ctx.accounts.maker.key() || signer == ctx.accounts.proposal.taker,
+        ErrorCode::InvalidUser
+    );
+    msg!("Counter offer on deal {:?} rejected", deal_id);
+    Ok(())
+}
@@ -0,0 +1,188 @@
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{Mint, TokenAccount, TokenInterface, TransferChecked},
+};
+
+use crate::extensions::transfer_checked_with_hook;
+use crate::escrow_accounts::{close_escrows, leg_mint, ControllerSeeds};
+use crate::native::{native_escrow_amount, transfer_from_controller};
+use crate::order_index::unindex_deal;
+use crate::{DealDetails, ErrorCode, FillDetails, OrderIndex, UserEscrowDetails, BPS_DENOMINATOR};
+
+// The arbiter settles an open dispute. split_bps of the escrowed mint_a goes to the taker and the same share
+// of the escrowed mint_b to the maker, the rest of each goes back to whoever paid it in. Closes the deal like take
+#[derive(Accounts)]
+#[instruction(deal_id: u64)]
+pub struct ResolveDispute<'info> {
+    // pays for any payout account that does not exist yet
+    #[account(mut)]
+    pub arbiter: Signer<'info>,
+
+    /// CHECK: just used as a public key wallet, receives its share and the rent of the closed accounts
+    #[account(mut)]
+    pub maker: AccountInfo<'info>,
+
+    /// CHECK: checked against the deal's taker, receives its share
+    #[account(mut, address = deal_details.taker.ok_or(ErrorCode::InvalidUser)? @ ErrorCode::InvalidUser)]
+    pub taker: AccountInfo<'info>,
+
+    #[account(mut, seeds=[b"deal", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.deal_details_bump, close=maker)]
+    pub deal_details: Account<'info, DealDetails>,
+
+    #[account(mut, seeds=[b"controller", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.escrow_token_controller_bump)]
+    pub escrow_token_controller: SystemAccount<'info>,
+
+    // mints and token accounts of a native SOL leg are left out
+    #[account(address = user_a_details.mint @ ErrorCode::InvalidMint, mint::token_program = token_program_a)]
+    pub mint_a: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(address = user_b_details.mint @ ErrorCode::InvalidMint, mint::token_program = token_program_b)]
+    pub mint_b: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut, seeds=[b"user_a_details", deal_details.key().as_ref()], bump=user_a_details.user_details_bump, close=maker)]
+    pub user_a_details: Account<'info, UserEscrowDetails>,
+
+    #[account(mut, seeds=[b"user_b_details", deal_details.key().as_ref()], bump=user_b_details.user_details_bump, close=maker)]
+    pub user_b_details: Account<'info, UserEscrowDetails>,
+
+    #[account(mut, seeds=[b"token_a", deal_details.key().as_ref()], bump=user_a_details.escrow_token_acc_bump)]
+    pub escrow_token_acc_a: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, seeds=[b"token_b", deal_details.key().as_ref()], bump=user_b_details.escrow_token_acc_bump)]
+    pub escrow_token_acc_b: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer=arbiter,
+        associated_token::mint = mint_a,
+        associated_token::authority = maker,
+        associated_token::token_program = token_program_a
+    )]
+    pub maker_token_acc_a: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer=arbiter,
+        associated_token::mint = mint_b,
+        associated_token::authority = maker,
+        associated_token::token_program = token_program_b
+    )]
+    pub maker_token_acc_b: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer=arbiter,
+        associated_token::mint = mint_a,
+        associated_token::authority = taker,
+        associated_token::token_program = token_program_a
+    )]
+    pub taker_token_acc_a: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer=arbiter,
+        associated_token::mint = mint_b,
+        associated_token::authority = taker,
+        associated_token::token_program = token_program_b
+    )]
+    pub taker_token_acc_b: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // what the taker filled for is part of the split, the record goes away with the deal
+    #[account(mut, seeds=[b"fill", deal_details.key().as_ref(), taker.key().as_ref()], bump=fill_details.fill_details_bump, close=taker)]
+    pub fill_details: Option<Account<'info, FillDetails>>,
+
+    // only when the deal is listed in the order index of its mint pair
+    #[account(mut)]
+    pub order_index: Option<Account<'info, OrderIndex>>,
+
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+pub fn handler<'info>(ctx: Context<'_, '_, '_, 'info, ResolveDispute<'info>>, split_bps: u16) -> Result<()> {
+    require_keys_eq!(
+        ctx.accounts.arbiter.key(),
+        ctx.accounts.deal_details.arbiter.ok_or(ErrorCode::NoArbiter)?,
+        ErrorCode::InvalidUser
+    );
+    require!(ctx.accounts.deal_details.dispute_open, ErrorCode::NoDisputeOpen);
+    require!(split_bps <= BPS_DENOMINATOR, ErrorCode::InvalidSplit);
+
+    // every token escrow has to be passed in, the deal accounts are closed at the end
+    require!(
+        ctx.accounts.escrow_token_acc_a.is_some() || ctx.accounts.user_a_details.is_native(),
+        ErrorCode::MissingTokenAccount
+    );
+    require!(
+        ctx.accounts.escrow_token_acc_b.is_some() || ctx.accounts.user_b_details.is_native(),
+        ErrorCode::MissingTokenAccount
+    );
+
+    unindex_deal(&mut ctx.accounts.deal_details, &mut ctx.accounts.order_index)?;
+
+    let controller_signer = ControllerSeeds::new(&ctx.accounts.deal_details);
+    let controller_seeds: &[&[&[u8]]] = &[&controller_signer.seeds()];
+
+    // a native SOL leg is whatever the controller holds above its reserve, at most one side can be native
+    let escrowed_a = match &ctx.accounts.escrow_token_acc_a {
+        Some(escrow_token_acc_a) => escrow_token_acc_a.amount,
+        None => native_escrow_amount(&ctx.accounts.escrow_token_controller)?,
+    };
+    let escrowed_b = match &ctx.accounts.escrow_token_acc_b {
+        Some(escrow_token_acc_b) => escrow_token_acc_b.amount,
+        None => native_escrow_amount(&ctx.accounts.escrow_token_controller)?,
+    };
+    let taker_share_a = (escrowed_a as u128 * split_bps as u128 / BPS_DENOMINATOR as u128) as u64;
+    let maker_share_b = (escrowed_b as u128 * split_bps as u128 / BPS_DENOMINATOR as u128) as u64;
+
+    let accounts = &ctx.accounts;
+    let mint_a = leg_mint(&accounts.mint_a, &accounts.user_a_details)?;
+    let mint_b = leg_mint(&accounts.mint_b, &accounts.user_b_details)?;
+    let payouts = [
+        (mint_a, &accounts.escrow_token_acc_a, &accounts.token_program_a, &accounts.taker_token_acc_a, &accounts.taker, taker_share_a),
+        (mint_a, &accounts.escrow_token_acc_a, &accounts.token_program_a, &accounts.maker_token_acc_a, &accounts.maker, escrowed_a - taker_share_a),
+        (mint_b, &accounts.escrow_token_acc_b, &accounts.token_program_b, &accounts.maker_token_acc_b, &accounts.maker, maker_share_b),
+        (mint_b, &accounts.escrow_token_acc_b, &accounts.token_program_b, &accounts.taker_token_acc_b, &accounts.taker, escrowed_b - maker_share_b),
+    ];
+    for (mint, escrow_token_acc, token_program, user_token_acc, user, amount) in payouts {
+        if amount == 0 {
+            continue;
+        }
+        match mint {
+            Some(mint) => {
+                let cpi_accounts = TransferChecked {
+                    mint: mint.to_account_info(),
+                    from: escrow_token_acc.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+                    to: user_token_acc.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+                    authority: accounts.escrow_token_controller.to_account_info(),
+                };
+                let cpi_context = CpiContext::new(token_program.to_account_info(), cpi_accounts).with_signer(controller_seeds).with_remaining_accounts(ctx.remaining_accounts.to_vec());
+                transfer_checked_with_hook(cpi_context, amount, mint.decimals)?;
+            }
+            None => transfer_from_controller(
+                &accounts.escrow_token_controller.to_account_info(),
+                user,
+                &accounts.system_program.to_account_info(),
+                controller_seeds,
+                amount,
+            )?,
+        }
+    }
+
+    close_escrows(
+        [
+            (&accounts.escrow_token_acc_a, &accounts.token_program_a),
+            (&accounts.escrow_token_acc_b, &accounts.token_program_b),
+        ],
+        &accounts.maker,
+        &accounts.escrow_token_controller.to_account_info(),
+        &accounts.system_program.to_account_info(),
+        controller_seeds,
+    )?;
+
+    msg!("Dispute on deal {:?} resolved, {:?} bps to the taker", accounts.deal_details.deal_id, split_bps);
+    Ok(())
+}
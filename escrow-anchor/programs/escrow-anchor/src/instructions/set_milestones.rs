@@ -0,0 +1,41 @@
+use anchor_lang::prelude::*;
+
+use crate::{DealDetails, DealState, ErrorCode, UserEscrowDetails, MAX_MILESTONES};
+
+// Splits the maker side into ordered tranches, the taker only collects a tranche once it is approved
+#[derive(Accounts)]
+#[instruction(deal_id: u64)]
+pub struct SetMilestones<'info> {
+    pub maker: Signer<'info>,
+
+    #[account(seeds=[b"deal", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.deal_details_bump)]
+    pub deal_details: Account<'info, DealDetails>,
+
+    #[account(mut, seeds=[b"user_a_details", deal_details.key().as_ref()], bump=user_a_details.user_details_bump)]
+    pub user_a_details: Account<'info, UserEscrowDetails>,
+}
+
+pub fn handler(ctx: Context<SetMilestones>, amounts: Vec<u64>) -> Result<()> {
+    // tranches are released to one taker, so it has to be known up front
+    require!(ctx.accounts.deal_details.taker.is_some(), ErrorCode::MilestonesRequireTaker);
+    require!(ctx.accounts.deal_details.state == DealState::Created, ErrorCode::InvalidDealState);
+    // basket legs are released in full on the first withdraw, they can't follow the tranches
+    require!(ctx.accounts.deal_details.basket_legs == 0, ErrorCode::MilestonesWithBasket);
+
+    let total = amounts.iter().try_fold(0u64, |total, amount| total.checked_add(*amount));
+    require!(
+        !amounts.is_empty()
+            && amounts.len() <= MAX_MILESTONES as usize
+            && amounts.iter().all(|amount| *amount > 0)
+            && total == Some(ctx.accounts.user_a_details.mint_amt),
+        ErrorCode::InvalidMilestones
+    );
+
+    let user_a_details = &mut ctx.accounts.user_a_details;
+    user_a_details.milestones = amounts;
+    user_a_details.milestones_released = 0;
+    user_a_details.released_amt = 0;
+
+    msg!("Deal {:?} split into {:?} milestones", ctx.accounts.deal_details.deal_id, user_a_details.milestones.len());
+    Ok(())
+}
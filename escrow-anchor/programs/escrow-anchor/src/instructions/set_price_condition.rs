@@ -0,0 +1,32 @@
+use anchor_lang::prelude::*;
+
+use crate::oracle::read_price;
+use crate::{DealDetails, DealState, ErrorCode, PriceCondition};
+
+// Makes the deal fill only while the price in the oracle account sits within [min_price, max_price]
+#[derive(Accounts)]
+#[instruction(deal_id: u64)]
+pub struct SetPriceCondition<'info> {
+    pub maker: Signer<'info>,
+
+    #[account(mut, seeds=[b"deal", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.deal_details_bump)]
+    pub deal_details: Account<'info, DealDetails>,
+
+    /// CHECK: any account laid out as a price account, read once here to make sure it parses
+    pub oracle: UncheckedAccount<'info>,
+}
+
+pub fn handler(ctx: Context<SetPriceCondition>, min_price: i64, max_price: i64) -> Result<()> {
+    // takers that already filled did so on the old terms
+    require!(ctx.accounts.deal_details.state == DealState::Created, ErrorCode::InvalidDealState);
+    require!(min_price <= max_price, ErrorCode::InvalidPriceCondition);
+    read_price(&ctx.accounts.oracle)?;
+
+    ctx.accounts.deal_details.price_condition = Some(PriceCondition {
+        oracle: ctx.accounts.oracle.key(),
+        min_price,
+        max_price,
+    });
+    msg!("Deal {:?} fills between {:?} and {:?}", ctx.accounts.deal_details.deal_id, min_price, max_price);
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use anchor_lang::prelude::*;
+
+use crate::{DealDetails, DealState, ErrorCode, TakerAllowlist, MAX_ALLOWED_TAKERS};
+
+// Restricts an open offer to a group of takers, either listed directly or through a Merkle root
+#[derive(Accounts)]
+#[instruction(deal_id: u64)]
+pub struct SetTakerAllowlist<'info> {
+    pub maker: Signer<'info>,
+
+    #[account(mut, seeds=[b"deal", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.deal_details_bump)]
+    pub deal_details: Account<'info, DealDetails>,
+}
+
+pub fn handler(ctx: Context<SetTakerAllowlist>, allowlist: TakerAllowlist) -> Result<()> {
+    // takers that already filled were let in on the old list
+    require!(ctx.accounts.deal_details.state == DealState::Created, ErrorCode::InvalidDealState);
+    // a fixed taker already limits the deal to one wallet
+    require!(ctx.accounts.deal_details.taker.is_none(), ErrorCode::InvalidAllowlist);
+    if let TakerAllowlist::Takers { takers } = &allowlist {
+        require!(
+            !takers.is_empty() && takers.len() <= MAX_ALLOWED_TAKERS as usize,
+            ErrorCode::InvalidAllowlist
+        );
+    }
+
+    ctx.accounts.deal_details.taker_allowlist = Some(allowlist);
+    msg!("Deal {:?} restricted to an allowlist of takers", ctx.accounts.deal_details.deal_id);
+    Ok(())
+}
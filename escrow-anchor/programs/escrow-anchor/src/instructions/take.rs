@@ -0,0 +1,209 @@
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{Mint, TokenAccount, TokenInterface, TransferChecked},
+};
+
+use crate::allowlist::require_allowed_taker;
+use crate::oracle::require_price_condition;
+use crate::order_index::unindex_deal;
+use crate::extensions::{gross_amount, transfer_checked_with_hook, transfer_fee};
+use crate::escrow_accounts::{close_escrows, leg_mint, ControllerSeeds};
+use crate::native::{native_escrow_amount, transfer_from_controller};
+use crate::{DealDetails, DealState, ErrorCode, OrderIndex, UserEscrowDetails};
+
+// Settles a deal in a single instruction, the taker pays the maker directly and receives the escrowed mint_a.
+// All deal accounts are closed afterwards and the rent goes back to the maker
+#[derive(Accounts)]
+#[instruction(deal_id: u64)]
+pub struct Take<'info> {
+    #[account(mut)]
+    pub taker: Signer<'info>,
+
+    /// CHECK: just used as a public key wallet, receives mint_b and the rent of the closed accounts
+    #[account(mut)]
+    pub maker: AccountInfo<'info>,
+
+    #[account(mut, seeds=[b"deal", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.deal_details_bump, close=maker)]
+    pub deal_details: Account<'info, DealDetails>,
+
+    #[account(mut, seeds=[b"controller", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], bump=deal_details.escrow_token_controller_bump)]
+    pub escrow_token_controller: SystemAccount<'info>,
+
+    // mints and token accounts of a native SOL leg are left out
+    #[account(address = user_a_details.mint @ ErrorCode::InvalidMint, mint::token_program = token_program_a)]
+    pub mint_a: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(address = user_b_details.mint @ ErrorCode::InvalidMint, mint::token_program = token_program_b)]
+    pub mint_b: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut, seeds=[b"user_a_details", deal_details.key().as_ref()], bump=user_a_details.user_details_bump, close=maker)]
+    pub user_a_details: Account<'info, UserEscrowDetails>,
+
+    #[account(mut, seeds=[b"user_b_details", deal_details.key().as_ref()], bump=user_b_details.user_details_bump, close=maker)]
+    pub user_b_details: Account<'info, UserEscrowDetails>,
+
+    #[account(mut, seeds=[b"token_a", deal_details.key().as_ref()], bump=user_a_details.escrow_token_acc_bump)]
+    pub escrow_token_acc_a: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, seeds=[b"token_b", deal_details.key().as_ref()], bump=user_b_details.escrow_token_acc_bump)]
+    pub escrow_token_acc_b: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // taker receives mint_a here
+    #[account(
+        init_if_needed,
+        payer=taker,
+        associated_token::mint = mint_a,
+        associated_token::authority = taker,
+        associated_token::token_program = token_program_a
+    )]
+    pub taker_token_acc_a: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // taker pays mint_b from here
+    #[account(
+        mut,
+        associated_token::mint = mint_b,
+        associated_token::authority = taker,
+        associated_token::token_program = token_program_b
+    )]
+    pub taker_token_acc_b: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // maker receives mint_b here
+    #[account(
+        init_if_needed,
+        payer=taker,
+        associated_token::mint = mint_b,
+        associated_token::authority = maker,
+        associated_token::token_program = token_program_b
+    )]
+    pub maker_token_acc_b: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: only for a price conditional deal, checked against the oracle in its condition
+    pub price_oracle: Option<UncheckedAccount<'info>>,
+
+    // only when the deal is listed in the order index of its mint pair
+    #[account(mut)]
+    pub order_index: Option<Account<'info, OrderIndex>>,
+}
+
+pub fn handler<'info>(ctx: Context<'_, '_, '_, 'info, Take<'info>>, expected_maker_amt: u64, max_taker_amt: u64, proof: Vec<[u8; 32]>) -> Result<()> {
+    if let Some(taker) = ctx.accounts.deal_details.taker {
+        require_keys_eq!(taker, ctx.accounts.taker.key(), ErrorCode::InvalidUser);
+    }
+    require_allowed_taker(&ctx.accounts.deal_details.taker_allowlist, ctx.accounts.taker.key, &proof)?;
+
+    // the taker pays what they saw in their UI at most, and gets exactly the mint_a they saw
+    require!(
+        ctx.accounts.user_a_details.mint_amt == expected_maker_amt && ctx.accounts.user_b_details.mint_amt <= max_taker_amt,
+        ErrorCode::TermsChanged
+    );
+
+    require!(
+        Clock::get()?.unix_timestamp < ctx.accounts.deal_details.expires_at,
+        ErrorCode::DealExpired
+    );
+
+    // a deal settled through deposit has to go through withdraw instead
+    let state = ctx.accounts.deal_details.state;
+    require!(!state.is_filled(), ErrorCode::DealAlreadyFulfilled);
+    // escrow_token_acc_a holds mint_a owed to earlier takers once anyone filled part of the deal
+    require!(state != DealState::PartiallyFilled, ErrorCode::DealPartiallyFilled);
+    require!(state == DealState::Created, ErrorCode::InvalidDealState);
+    // take closes the deal in one go, basket legs would have to be settled alongside it
+    require!(ctx.accounts.deal_details.basket_legs == 0, ErrorCode::BasketNotSupported);
+    require!(ctx.accounts.user_a_details.milestones.is_empty(), ErrorCode::MilestoneDeal);
+    require_price_condition(
+        &ctx.accounts.deal_details.price_condition,
+        ctx.accounts.price_oracle.as_ref().map(|oracle| oracle.as_ref()),
+    )?;
+
+    let controller_signer = ControllerSeeds::new(&ctx.accounts.deal_details);
+    let controller_seeds: &[&[&[u8]]] = &[&controller_signer.seeds()];
+
+    // every token escrow has to be passed in, the deal accounts are closed at the end
+    require!(
+        ctx.accounts.escrow_token_acc_b.is_some() || ctx.accounts.user_b_details.is_native(),
+        ErrorCode::MissingTokenAccount
+    );
+
+    // Pay the maker straight from the taker's account
+    match leg_mint(&ctx.accounts.mint_b, &ctx.accounts.user_b_details)? {
+        Some(mint_b) => {
+            let cpi_accounts = TransferChecked {
+                mint: mint_b.to_account_info(),
+                from: ctx.accounts.taker_token_acc_b.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+                to: ctx.accounts.maker_token_acc_b.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+                authority: ctx.accounts.taker.to_account_info(),
+            };
+            let cpi_context = CpiContext::new(ctx.accounts.token_program_b.to_account_info(), cpi_accounts).with_remaining_accounts(ctx.remaining_accounts.to_vec());
+            let gross_taker_amt = gross_amount(mint_b.as_ref(), ctx.accounts.user_b_details.mint_amt)?;
+            transfer_checked_with_hook(cpi_context, gross_taker_amt, mint_b.decimals)?;
+        }
+        None => {
+            let cpi_accounts = anchor_lang::system_program::Transfer {
+                from: ctx.accounts.taker.to_account_info(),
+                to: ctx.accounts.maker.to_account_info(),
+            };
+            let cpi_context = CpiContext::new(ctx.accounts.system_program.to_account_info(), cpi_accounts);
+            anchor_lang::system_program::transfer(cpi_context, ctx.accounts.user_b_details.mint_amt)?;
+        }
+    }
+
+    // Release everything held for the maker side to the taker
+    match leg_mint(&ctx.accounts.mint_a, &ctx.accounts.user_a_details)? {
+        Some(mint_a) => {
+            let escrow_token_acc_a = ctx.accounts.escrow_token_acc_a.as_ref().ok_or(ErrorCode::MissingTokenAccount)?;
+            let cpi_accounts = TransferChecked {
+                mint: mint_a.to_account_info(),
+                from: escrow_token_acc_a.to_account_info(),
+                to: ctx.accounts.taker_token_acc_a.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+                authority: ctx.accounts.escrow_token_controller.to_account_info(),
+            };
+            let cpi_context = CpiContext::new(ctx.accounts.token_program_a.to_account_info(), cpi_accounts).with_signer(controller_seeds).with_remaining_accounts(ctx.remaining_accounts.to_vec());
+            transfer_checked_with_hook(cpi_context, escrow_token_acc_a.amount, mint_a.decimals)?;
+            msg!("Transfer fee withheld from taker payout: {:?}", transfer_fee(mint_a.as_ref(), escrow_token_acc_a.amount)?);
+        }
+        None => transfer_from_controller(
+            &ctx.accounts.escrow_token_controller.to_account_info(),
+            &ctx.accounts.taker.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            controller_seeds,
+            native_escrow_amount(&ctx.accounts.escrow_token_controller)?,
+        )?,
+    }
+
+    // escrow_token_acc_b is normally empty here, anything sent to it still belongs to the maker
+    if let (Some(mint_b), Some(escrow_token_acc_b)) = (&ctx.accounts.mint_b, &ctx.accounts.escrow_token_acc_b) {
+        if escrow_token_acc_b.amount > 0 {
+            let cpi_accounts = TransferChecked {
+                mint: mint_b.to_account_info(),
+                from: escrow_token_acc_b.to_account_info(),
+                to: ctx.accounts.maker_token_acc_b.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+                authority: ctx.accounts.escrow_token_controller.to_account_info(),
+            };
+            let cpi_context = CpiContext::new(ctx.accounts.token_program_b.to_account_info(), cpi_accounts).with_signer(controller_seeds).with_remaining_accounts(ctx.remaining_accounts.to_vec());
+            transfer_checked_with_hook(cpi_context, escrow_token_acc_b.amount, mint_b.decimals)?;
+        }
+    }
+
+    close_escrows(
+        [
+            (&ctx.accounts.escrow_token_acc_a, &ctx.accounts.token_program_a),
+            (&ctx.accounts.escrow_token_acc_b, &ctx.accounts.token_program_b),
+        ],
+        &ctx.accounts.maker,
+        &ctx.accounts.escrow_token_controller.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+        controller_seeds,
+    )?;
+
+    unindex_deal(&mut ctx.accounts.deal_details, &mut ctx.accounts.order_index)?;
+    ctx.accounts.deal_details.state = DealState::Completed;
+    msg!("Deal {:?} taken by {:?}", ctx.accounts.deal_details.deal_id, ctx.accounts.taker.key());
+    Ok(())
+}
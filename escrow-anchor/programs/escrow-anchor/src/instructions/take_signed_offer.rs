@@ -0,0 +1,125 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::sysvar;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{Mint, TokenAccount, TokenInterface, TransferChecked},
+};
+
+use crate::ed25519::require_ed25519_signature;
+use crate::extensions::{gross_amount, transfer_checked_with_hook};
+use crate::{ErrorCode, OfferNonce, SignedOffer};
+
+// Settles an offer the maker signed off-chain. The maker's mint_a moves through the delegate PDA they approved
+// on their token account, so the maker pays no rent and locks nothing up until someone takes the offer
+#[derive(Accounts)]
+#[instruction(offer: SignedOffer)]
+pub struct TakeSignedOffer<'info> {
+    #[account(mut)]
+    pub taker: Signer<'info>,
+
+    /// CHECK: just used as a public key wallet, the signature is checked against it
+    #[account(address = offer.maker @ ErrorCode::InvalidUser)]
+    pub maker: AccountInfo<'info>,
+
+    // maker approves this PDA as delegate of maker_token_acc_a for what they are willing to sell
+    #[account(seeds=[b"delegate", maker.key().as_ref()], bump)]
+    pub offer_delegate: SystemAccount<'info>,
+
+    // init fails for a nonce that was already used, so a signed offer can't be replayed
+    #[account(
+        init,
+        payer=taker,
+        seeds=[b"nonce", maker.key().as_ref(), offer.nonce.to_le_bytes().as_ref()],
+        space=8+OfferNonce::INIT_SPACE,
+        bump
+    )]
+    pub offer_nonce: Account<'info, OfferNonce>,
+
+    #[account(address = offer.mint_a @ ErrorCode::InvalidMint, mint::token_program = token_program_a)]
+    pub mint_a: InterfaceAccount<'info, Mint>,
+
+    #[account(address = offer.mint_b @ ErrorCode::InvalidMint, mint::token_program = token_program_b)]
+    pub mint_b: InterfaceAccount<'info, Mint>,
+
+    // maker pays mint_a from here through the delegate
+    #[account(
+        mut,
+        associated_token::mint = mint_a,
+        associated_token::authority = maker,
+        associated_token::token_program = token_program_a
+    )]
+    pub maker_token_acc_a: InterfaceAccount<'info, TokenAccount>,
+
+    // taker receives mint_a here
+    #[account(
+        init_if_needed,
+        payer=taker,
+        associated_token::mint = mint_a,
+        associated_token::authority = taker,
+        associated_token::token_program = token_program_a
+    )]
+    pub taker_token_acc_a: InterfaceAccount<'info, TokenAccount>,
+
+    // taker pays mint_b from here
+    #[account(
+        mut,
+        associated_token::mint = mint_b,
+        associated_token::authority = taker,
+        associated_token::token_program = token_program_b
+    )]
+    pub taker_token_acc_b: InterfaceAccount<'info, TokenAccount>,
+
+    // maker receives mint_b here
+    #[account(
+        init_if_needed,
+        payer=taker,
+        associated_token::mint = mint_b,
+        associated_token::authority = maker,
+        associated_token::token_program = token_program_b
+    )]
+    pub maker_token_acc_b: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: the instructions sysvar, read for the Ed25519 instruction before this one
+    #[account(address = sysvar::instructions::ID)]
+    pub instructions_sysvar: UncheckedAccount<'info>,
+
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+pub fn handler<'info>(ctx: Context<'_, '_, '_, 'info, TakeSignedOffer<'info>>, offer: SignedOffer) -> Result<()> {
+    require!(Clock::get()?.unix_timestamp < offer.expires_at, ErrorCode::DealExpired);
+    require!(offer.maker_amt > 0 && offer.taker_amt > 0, ErrorCode::InvalidAmount);
+    require_keys_neq!(offer.mint_a, offer.mint_b, ErrorCode::IdenticalMints);
+    require_ed25519_signature(&ctx.accounts.instructions_sysvar, &offer.maker, &offer.message()?)?;
+
+    ctx.accounts.offer_nonce.offer_nonce_bump = ctx.bumps.offer_nonce;
+    ctx.accounts.offer_nonce.payer = ctx.accounts.taker.key();
+    ctx.accounts.offer_nonce.expires_at = offer.expires_at;
+
+    // Pay the maker straight from the taker's account, the taker covers any transfer fee
+    let cpi_accounts = TransferChecked {
+        mint: ctx.accounts.mint_b.to_account_info(),
+        from: ctx.accounts.taker_token_acc_b.to_account_info(),
+        to: ctx.accounts.maker_token_acc_b.to_account_info(),
+        authority: ctx.accounts.taker.to_account_info(),
+    };
+    let cpi_context = CpiContext::new(ctx.accounts.token_program_b.to_account_info(), cpi_accounts).with_remaining_accounts(ctx.remaining_accounts.to_vec());
+    transfer_checked_with_hook(cpi_context, gross_amount(ctx.accounts.mint_b.as_ref(), offer.taker_amt)?, ctx.accounts.mint_b.decimals)?;
+
+    // and the taker from the maker's account, the maker covers the fee so the taker gets maker_amt like from an escrow
+    let delegate_seeds: &[&[&[u8]]] = &[&[b"delegate", ctx.accounts.maker.key.as_ref(), &[ctx.bumps.offer_delegate]]];
+    let cpi_accounts = TransferChecked {
+        mint: ctx.accounts.mint_a.to_account_info(),
+        from: ctx.accounts.maker_token_acc_a.to_account_info(),
+        to: ctx.accounts.taker_token_acc_a.to_account_info(),
+        authority: ctx.accounts.offer_delegate.to_account_info(),
+    };
+    let cpi_context = CpiContext::new(ctx.accounts.token_program_a.to_account_info(), cpi_accounts).with_signer(delegate_seeds).with_remaining_accounts(ctx.remaining_accounts.to_vec());
+    transfer_checked_with_hook(cpi_context, gross_amount(ctx.accounts.mint_a.as_ref(), offer.maker_amt)?, ctx.accounts.mint_a.decimals)?;
+
+    msg!("Signed offer {:?} of {:?} taken by {:?}", offer.nonce, offer.maker, ctx.accounts.taker.key());
+    Ok(())
+}
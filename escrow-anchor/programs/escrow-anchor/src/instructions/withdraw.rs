@@ -1,111 +1,326 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token_interface::{
-    transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked,
-};
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface, TransferChecked};
 
-use crate::DealDetails;
+use crate::basket::{basket_legs, hook_accounts, leg_accounts, release_leg};
+use crate::extensions::{transfer_checked_with_hook, transfer_fee};
+use crate::escrow_accounts::{close_escrows, sweep_escrow, ControllerSeeds};
+use crate::native::{is_native_mint, native_escrow_amount, transfer_from_controller, unwrap_to, UnwrapAccounts};
+use crate::order_index::unindex_deal;
+use crate::{BasketDetails, DealDetails, DealState, FillDetails, LegSide, OrderIndex, UserEscrowDetails};
 use crate::ErrorCode;
 
 #[derive(Accounts)]
+#[instruction(deal_id: u64)]
 pub struct Withdraw<'info> {
+    #[account(mut)]
     pub signer: Signer<'info>,
 
-    /// CHECK: just used as a public key wallet, doesnt need validation
+    /// CHECK: just used as a public key wallet, gets the rent back once the last withdraw closes the deal
+    #[account(mut)]
     pub maker: AccountInfo<'info>,
 
-    /// CHECK: just used as a public key wallet, doesnt need validation
-    pub taker: AccountInfo<'info>,
-
     #[account(
-        seeds=[b"deal", maker.key().as_ref()], 
+        mut,
+        seeds=[b"deal", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], 
         bump=deal_details.deal_details_bump
     )]
     pub deal_details: Account<'info, DealDetails>,
 
+    // holds the lamports of a native SOL leg
     #[account(
-        seeds=[b"controller", maker.key().as_ref()], 
+        mut,
+        seeds=[b"controller", maker.key().as_ref(), deal_id.to_le_bytes().as_ref()], 
         bump=deal_details.escrow_token_controller_bump
     )]
     pub escrow_token_controller: SystemAccount<'info>,
 
     #[account(
         mut,
-        seeds=[b"token", maker.key().as_ref()],
+        seeds=[b"token_a", deal_details.key().as_ref()],
         token::mint=mint_a,
         token::authority=escrow_token_controller,
-        bump
+        token::token_program=token_program_a,
+        bump=user_a_details.escrow_token_acc_bump
     )]
-    pub escrow_token_acc_a: InterfaceAccount<'info, TokenAccount>,
+    pub escrow_token_acc_a: Option<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(
         mut,
-        seeds=[b"token", taker.key().as_ref()],
+        seeds=[b"token_b", deal_details.key().as_ref()],
         token::mint=mint_b,
         token::authority=escrow_token_controller,
-        bump
+        token::token_program=token_program_b,
+        bump=user_b_details.escrow_token_acc_bump
     )]
-    pub escrow_token_acc_b: InterfaceAccount<'info, TokenAccount>,
+    pub escrow_token_acc_b: Option<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(
         mut,
         token::mint=mint_exchange,
-        token::authority=signer,
-        token::token_program=token_program
+        token::authority=signer
     )]
-    pub user_token_acc: InterfaceAccount<'info, TokenAccount>,
+    pub user_token_acc: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // any of the maker's accounts for either leg, only needed when tokens were sent into an escrow on top of the deal.
+    // The last withdraw sweeps them to the maker so it can still close the deal
+    #[account(mut, token::mint = mint_a, token::authority = maker, token::token_program = token_program_a)]
+    pub maker_token_acc_a: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, token::mint = mint_b, token::authority = maker, token::token_program = token_program_b)]
+    pub maker_token_acc_b: Option<InterfaceAccount<'info, TokenAccount>>,
     
-    #[account(mint::token_program=token_program)]
-    pub mint_a: InterfaceAccount<'info, Mint>,
+    // mints and token accounts of a native SOL leg are left out, the signer is paid in lamports instead
+    #[account(address = user_a_details.mint @ ErrorCode::InvalidMint, mint::token_program=token_program_a)]
+    pub mint_a: Option<InterfaceAccount<'info, Mint>>,
     
-    #[account(mint::token_program=token_program)]
-    pub mint_b: InterfaceAccount<'info, Mint>,
+    #[account(address = user_b_details.mint @ ErrorCode::InvalidMint, mint::token_program=token_program_b)]
+    pub mint_b: Option<InterfaceAccount<'info, Mint>>,
     
-    #[account(mint::token_program=token_program)]
-    pub mint_exchange: InterfaceAccount<'info, Mint>,
+    // mint the signer is receiving, mint_b for the maker and mint_a for a taker.
+    // Its token program depends on the leg and is checked in the handler
+    pub mint_exchange: Option<InterfaceAccount<'info, Mint>>,
 
-    pub token_program: Interface<'info, TokenInterface>,
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+
+    #[account(mut, seeds=[b"user_a_details", deal_details.key().as_ref()], bump=user_a_details.user_details_bump)]
+    pub user_a_details: Account<'info, UserEscrowDetails>,
+
+    #[account(mut, seeds=[b"user_b_details", deal_details.key().as_ref()], bump=user_b_details.user_details_bump)]
+    pub user_b_details: Account<'info, UserEscrowDetails>,
+
+    // only needed when a taker withdraws, holds the share of mint_a they filled for
+    #[account(
+        mut,
+        seeds=[b"fill", deal_details.key().as_ref(), signer.key().as_ref()],
+        bump=fill_details.fill_details_bump
+    )]
+    pub fill_details: Option<Account<'info, FillDetails>>,
+
+    /// CHECK: created and closed again within the instruction to unwrap a wSOL payout, only needed when receiving wSOL
+    #[account(mut, seeds=[b"unwrap", deal_details.key().as_ref(), signer.key().as_ref()], bump)]
+    pub unwrap_token_acc: Option<UncheckedAccount<'info>>,
+
+    // only for a basket deal, the legs paid out to the signer are passed in remaining_accounts
+    #[account(mut)]
+    pub basket_details: Option<Account<'info, BasketDetails>>,
+
+    // only when the deal is still listed in the order index of its mint pair, needed once the deal closes
+    #[account(mut)]
+    pub order_index: Option<Account<'info, OrderIndex>>,
+
+    pub system_program: Program<'info, System>,
 }
 
-pub fn handler(ctx: Context<Withdraw>) -> Result<()> {
-    msg!("Withdrawing from: {:?}", ctx.program_id);
+pub fn handler<'info>(ctx: Context<'_, '_, '_, 'info, Withdraw<'info>>) -> Result<()> {
+    msg!("Withdrawing from: {:?}", ctx.accounts.deal_details.deal_id);
 
-    // return if is_fullfilled is false
-    require!(
-        ctx.accounts.deal_details.is_fullfilled == true,
-        ErrorCode::IncompleteDeal
-    );
+    // The maker collects whatever the takers have paid in so far, a taker collects the share they filled for
+    let is_maker = ctx.accounts.signer.key() == ctx.accounts.deal_details.maker;
+    let state = ctx.accounts.deal_details.state;
+    // the arbiter decides who gets what once a dispute is raised
+    require!(!ctx.accounts.deal_details.dispute_open, ErrorCode::DisputeOpen);
+    if is_maker {
+        require!(!ctx.accounts.deal_details.maker_claimed, ErrorCode::AlreadyClaimed);
+        require!(
+            matches!(state, DealState::PartiallyFilled | DealState::TakerDeposited | DealState::TakerWithdrew | DealState::Expired),
+            ErrorCode::InvalidDealState
+        );
+    } else {
+        require!(!ctx.accounts.deal_details.taker_claimed, ErrorCode::AlreadyClaimed);
+        require!(
+            matches!(state, DealState::PartiallyFilled | DealState::TakerDeposited | DealState::MakerWithdrew | DealState::Expired),
+            ErrorCode::InvalidDealState
+        );
+    }
 
-    require!(
-        ctx.accounts.signer.key() == ctx.accounts.deal_details.maker || ctx.accounts.signer.key() == ctx.accounts.deal_details.taker,
-        ErrorCode::InvalidUser
-    );
+    let expected_mint = if is_maker { ctx.accounts.user_b_details.mint } else { ctx.accounts.user_a_details.mint };
+    let mint_exchange_key = ctx.accounts.mint_exchange.as_ref().map(|mint| mint.key()).unwrap_or_default();
+    require_keys_eq!(mint_exchange_key, expected_mint, ErrorCode::InvalidMint);
+    let receives_native = expected_mint == Pubkey::default();
 
+    // the payout leg decides which token program moves the funds
+    let token_program = if is_maker { &ctx.accounts.token_program_b } else { &ctx.accounts.token_program_a };
+    if let Some(mint_exchange) = &ctx.accounts.mint_exchange {
+        require_keys_eq!(*mint_exchange.to_account_info().owner, token_program.key(), ErrorCode::InvalidTokenProgram);
+    }
+    if let Some(user_token_acc) = &ctx.accounts.user_token_acc {
+        require_keys_eq!(*user_token_acc.to_account_info().owner, token_program.key(), ErrorCode::InvalidTokenProgram);
+    }
 
-    let from_escrow_account;
-    if ctx.accounts.signer.key() == ctx.accounts.deal_details.maker.key() {
-        from_escrow_account = &ctx.accounts.escrow_token_acc_b;
-    }else{
-        from_escrow_account = &ctx.accounts.escrow_token_acc_a;
+    let withdraw_amount = if is_maker {
+        if receives_native {
+            native_escrow_amount(&ctx.accounts.escrow_token_controller)?
+        } else {
+            ctx.accounts.escrow_token_acc_b.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.amount
+        }
+    } else {
+        // tranches of a milestone deal that have not been approved yet stay in escrow
+        let fill_details = ctx.accounts.fill_details.as_ref().ok_or(ErrorCode::InvalidUser)?;
+        fill_details.maker_amt_owed.saturating_sub(ctx.accounts.user_a_details.remaining_amt())
+    };
+
+    // return if the other side has not paid anything in yet
+    require!(withdraw_amount > 0, ErrorCode::IncompleteDeal);
+
+    let legs = basket_legs(&ctx.accounts.deal_details, &ctx.accounts.basket_details)?.to_vec();
+    let hook_accounts = hook_accounts(ctx.remaining_accounts, &legs);
+
+    let controller_signer = ControllerSeeds::new(&ctx.accounts.deal_details);
+    let controller_seeds: &[&[&[u8]]] = &[&controller_signer.seeds()];
+
+    if receives_native {
+        transfer_from_controller(
+            &ctx.accounts.escrow_token_controller.to_account_info(),
+            &ctx.accounts.signer.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            controller_seeds,
+            withdraw_amount,
+        )?;
+    } else if is_native_mint(&expected_mint) {
+        let from_escrow_account = if is_maker { &ctx.accounts.escrow_token_acc_b } else { &ctx.accounts.escrow_token_acc_a };
+        let unwrap_accounts = UnwrapAccounts {
+            payer: ctx.accounts.signer.to_account_info(),
+            unwrap_token_acc: ctx.accounts.unwrap_token_acc.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+            mint: ctx.accounts.mint_exchange.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+            escrow_token_acc: from_escrow_account.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+            controller: ctx.accounts.escrow_token_controller.to_account_info(),
+            token_program: token_program.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+        };
+        unwrap_to(
+            &unwrap_accounts,
+            &ctx.accounts.signer.to_account_info(),
+            &ctx.accounts.deal_details.key(),
+            ctx.bumps.unwrap_token_acc.ok_or(ErrorCode::MissingTokenAccount)?,
+            controller_seeds,
+            withdraw_amount,
+        )?;
+    } else {
+        let from_escrow_account = if is_maker { &ctx.accounts.escrow_token_acc_b } else { &ctx.accounts.escrow_token_acc_a };
+        let mint_exchange = ctx.accounts.mint_exchange.as_ref().ok_or(ErrorCode::MissingTokenAccount)?;
+
+        let cpi_accounts = TransferChecked {
+            mint: mint_exchange.to_account_info(),
+            from: from_escrow_account.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+            to: ctx.accounts.user_token_acc.as_ref().ok_or(ErrorCode::MissingTokenAccount)?.to_account_info(),
+            authority: ctx.accounts.escrow_token_controller.to_account_info(),
+        };
+        let cpi_program = token_program.to_account_info();
+
+        let cpi_context = CpiContext::new(cpi_program, cpi_accounts).with_signer(controller_seeds).with_remaining_accounts(hook_accounts.to_vec());
+        transfer_checked_with_hook(cpi_context, withdraw_amount, mint_exchange.decimals)?;
+
+        // the payout itself is charged the mint's transfer fee, it ends up withheld in the signer's account
+        msg!("Transfer fee withheld: {:?}", transfer_fee(mint_exchange.as_ref(), withdraw_amount)?);
+    }
+
+    // basket legs go out together with the main leg, the maker collects the taker legs and the taker the maker legs
+    let deal_key = ctx.accounts.deal_details.key();
+    let receiving_side = if is_maker { LegSide::Taker } else { LegSide::Maker };
+    for (index, leg) in legs.iter().enumerate().filter(|(_, leg)| leg.side == receiving_side) {
+        let leg_accounts = leg_accounts(ctx.remaining_accounts, &deal_key, index, leg)?;
+        release_leg(
+            &leg_accounts,
+            ctx.accounts.signer.key,
+            &ctx.accounts.maker,
+            &ctx.accounts.escrow_token_controller.to_account_info(),
+            hook_accounts,
+            controller_seeds,
+        )?;
     }
 
-    // let mint_decimals = ctx.accounts.mint.decimals;
-    let total_holding_amount = from_escrow_account.amount;
+    // taker has been paid everything they filled for, give the rent back
+    if let Some(fill_details) = &mut ctx.accounts.fill_details {
+        if !is_maker {
+            fill_details.maker_amt_owed -= withdraw_amount;
+            if fill_details.maker_amt_owed == 0 {
+                fill_details.close(ctx.accounts.signer.to_account_info())?;
+            }
+        }
+    }
 
-    let controller_seeds: &[&[&[u8]]] = &[&[
-        b"controller",
-        ctx.accounts.maker.key.as_ref(),
-        &[ctx.accounts.deal_details.escrow_token_controller_bump],
-    ]];
+    // While the deal can still be filled both sides keep collecting, a claim is only final once nothing else can come in
+    let deal_details = &mut ctx.accounts.deal_details;
+    if is_maker {
+        deal_details.maker_claimed = state != DealState::PartiallyFilled;
+    } else {
+        deal_details.maker_amt_owed -= withdraw_amount;
+        deal_details.taker_claimed = state != DealState::PartiallyFilled && deal_details.maker_amt_owed == 0;
+    }
 
-    let cpi_accounts = TransferChecked {
-        mint: ctx.accounts.mint_exchange.to_account_info(),
-        from: from_escrow_account.to_account_info(),
-        to: ctx.accounts.user_token_acc.to_account_info(),
-        authority: ctx.accounts.escrow_token_controller.to_account_info(),
+    deal_details.state = match (deal_details.maker_claimed, deal_details.taker_claimed) {
+        (true, true) => DealState::Completed,
+        _ if state == DealState::PartiallyFilled || state == DealState::Expired => state,
+        (true, false) => DealState::MakerWithdrew,
+        (false, true) => DealState::TakerWithdrew,
+        (false, false) => state,
     };
-    let cpi_program = ctx.accounts.token_program.to_account_info();
+    msg!("Deal state : {:?}", deal_details.state);
+
+    // Both parties are done, close everything in the same instruction instead of waiting for the maker to call close.
+    // Tokens sent into an escrow on top of the deal are swept to the maker first, if the maker's account for them
+    // is not passed the deal stays open and close sweeps them instead
+    if deal_details.state == DealState::Completed {
+        // every leg escrow was closed as it was paid out, only the basket itself is left
+        if let Some(basket_details) = &ctx.accounts.basket_details {
+            basket_details.close(ctx.accounts.maker.to_account_info())?;
+            ctx.accounts.deal_details.basket_legs = 0;
+        }
+
+        // native SOL legs are fully paid out by now, anything left in the controller is swept to the maker
+        let escrow_a_closable = match &mut ctx.accounts.escrow_token_acc_a {
+            Some(escrow_token_acc_a) => {
+                escrow_token_acc_a.reload()?;
+                escrow_token_acc_a.amount == 0 || is_native_mint(&escrow_token_acc_a.mint) || ctx.accounts.maker_token_acc_a.is_some()
+            }
+            None => ctx.accounts.user_a_details.is_native(),
+        };
+        let escrow_b_closable = match &mut ctx.accounts.escrow_token_acc_b {
+            Some(escrow_token_acc_b) => {
+                escrow_token_acc_b.reload()?;
+                escrow_token_acc_b.amount == 0 || is_native_mint(&escrow_token_acc_b.mint) || ctx.accounts.maker_token_acc_b.is_some()
+            }
+            None => ctx.accounts.user_b_details.is_native(),
+        };
+
+        if escrow_a_closable && escrow_b_closable {
+            unindex_deal(&mut ctx.accounts.deal_details, &mut ctx.accounts.order_index)?;
 
-    let cpi_context = CpiContext::new(cpi_program, cpi_accounts).with_signer(controller_seeds);
-    transfer_checked(cpi_context, total_holding_amount, ctx.accounts.mint_exchange.decimals)?;
+            let escrows = [
+                (&ctx.accounts.escrow_token_acc_a, &ctx.accounts.mint_a, &ctx.accounts.maker_token_acc_a, &ctx.accounts.token_program_a),
+                (&ctx.accounts.escrow_token_acc_b, &ctx.accounts.mint_b, &ctx.accounts.maker_token_acc_b, &ctx.accounts.token_program_b),
+            ];
+            for (escrow_token_acc, mint, maker_token_acc, token_program) in escrows {
+                if let Some(escrow_token_acc) = escrow_token_acc {
+                    sweep_escrow(
+                        escrow_token_acc,
+                        mint.as_ref(),
+                        maker_token_acc.as_ref(),
+                        &ctx.accounts.escrow_token_controller.to_account_info(),
+                        token_program,
+                        controller_seeds,
+                        hook_accounts,
+                    )?;
+                }
+            }
+
+            close_escrows(
+                [
+                    (&ctx.accounts.escrow_token_acc_a, &ctx.accounts.token_program_a),
+                    (&ctx.accounts.escrow_token_acc_b, &ctx.accounts.token_program_b),
+                ],
+                &ctx.accounts.maker,
+                &ctx.accounts.escrow_token_controller.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+                controller_seeds,
+            )?;
+
+            ctx.accounts.user_a_details.close(ctx.accounts.maker.to_account_info())?;
+            ctx.accounts.user_b_details.close(ctx.accounts.maker.to_account_info())?;
+            ctx.accounts.deal_details.close(ctx.accounts.maker.to_account_info())?;
+            msg!("Deal closed, rent returned to maker");
+        }
+    }
     Ok(())
-}
\ No newline at end of file
+}
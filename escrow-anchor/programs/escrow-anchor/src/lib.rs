@@ -1,6 +1,18 @@
+#![allow(unexpected_cfgs)]
+#![allow(deprecated)]
+#![allow(ambiguous_glob_reexports)]
+
+pub mod allowlist;
+pub mod basket;
 pub mod constants;
+pub mod ed25519;
 pub mod error;
+pub mod escrow_accounts;
+pub mod extensions;
 pub mod instructions;
+pub mod native;
+pub mod oracle;
+pub mod order_index;
 pub mod state;
 
 use anchor_lang::prelude::*;
@@ -20,26 +32,93 @@ pub mod escrow_anchor {
         initialize::handler(ctx)
     }
 
-    pub fn check(ctx: Context<Check>) -> Result<()> {
+    pub fn check(ctx: Context<Check>, _deal_id: u64) -> Result<()> {
         check::handler(ctx)
     }
 
-    pub fn create(ctx: Context<Create>, maker_amt : u64, taker_amt: u64) -> Result<()> {
-        create::handler(ctx, maker_amt, taker_amt)
+    pub fn create<'info>(ctx: Context<'_, '_, '_, 'info, Create<'info>>, deal_id: u64, maker_amt : u64, taker_amt: u64, expires_at: i64, bind_first_taker: bool) -> Result<()> {
+        create::handler(ctx, deal_id, maker_amt, taker_amt, expires_at, bind_first_taker)
     }
 
-    pub fn deposit(ctx: Context<Deposit>) -> Result<()> {
-        let amount = ctx.accounts.user_b_details.mint_amt;
-        deposit::handler(ctx, amount)
+    pub fn deposit<'info>(ctx: Context<'_, '_, '_, 'info, Deposit<'info>>, _deal_id: u64, amount: u64, expected_maker_amt: u64, max_taker_amt: u64, proof: Vec<[u8; 32]>) -> Result<()> {
+        deposit::handler(ctx, amount, expected_maker_amt, max_taker_amt, proof)
     }
 
-    pub fn withdraw(ctx: Context<Withdraw>) -> Result<()> {
+    pub fn withdraw<'info>(ctx: Context<'_, '_, '_, 'info, Withdraw<'info>>, _deal_id: u64) -> Result<()> {
         withdraw::handler(ctx)
     }
 
-    pub fn close(ctx: Context<Close>) -> Result<()>{
-        require!(ctx.accounts.escrow_token_acc_a.amount <= 0, ErrorCode::AccountContainsFund);
-        require!(ctx.accounts.escrow_token_acc_b.amount <= 0, ErrorCode::AccountContainsFund);
+    pub fn take<'info>(ctx: Context<'_, '_, '_, 'info, Take<'info>>, _deal_id: u64, expected_maker_amt: u64, max_taker_amt: u64, proof: Vec<[u8; 32]>) -> Result<()> {
+        take::handler(ctx, expected_maker_amt, max_taker_amt, proof)
+    }
+
+    pub fn cancel<'info>(ctx: Context<'_, '_, '_, 'info, Cancel<'info>>, _deal_id: u64) -> Result<()> {
+        cancel::handler(ctx)
+    }
+
+    pub fn expire<'info>(ctx: Context<'_, '_, '_, 'info, Expire<'info>>, _deal_id: u64) -> Result<()> {
+        expire::handler(ctx)
+    }
+
+    pub fn add_leg<'info>(ctx: Context<'_, '_, '_, 'info, AddLeg<'info>>, _deal_id: u64, side: LegSide, amount: u64) -> Result<()> {
+        add_leg::handler(ctx, side, amount)
+    }
+
+    pub fn raise_dispute(ctx: Context<RaiseDispute>, _deal_id: u64) -> Result<()> {
+        raise_dispute::handler(ctx)
+    }
+
+    pub fn resolve_dispute<'info>(ctx: Context<'_, '_, '_, 'info, ResolveDispute<'info>>, _deal_id: u64, split_bps: u16) -> Result<()> {
+        resolve_dispute::handler(ctx, split_bps)
+    }
+
+    pub fn set_milestones(ctx: Context<SetMilestones>, _deal_id: u64, amounts: Vec<u64>) -> Result<()> {
+        set_milestones::handler(ctx, amounts)
+    }
+
+    pub fn approve_milestone(ctx: Context<ApproveMilestone>, _deal_id: u64, index: u8) -> Result<()> {
+        approve_milestone::handler(ctx, index)
+    }
+
+    pub fn set_price_condition(ctx: Context<SetPriceCondition>, _deal_id: u64, min_price: i64, max_price: i64) -> Result<()> {
+        set_price_condition::handler(ctx, min_price, max_price)
+    }
+
+    pub fn counter_offer(ctx: Context<CounterOffer>, _deal_id: u64, new_maker_amt: u64, new_taker_amt: u64, proof: Vec<[u8; 32]>) -> Result<()> {
+        counter_offer::handler(ctx, new_maker_amt, new_taker_amt, proof)
+    }
+
+    pub fn accept_counter<'info>(ctx: Context<'_, '_, '_, 'info, AcceptCounter<'info>>, _deal_id: u64) -> Result<()> {
+        accept_counter::handler(ctx)
+    }
+
+    pub fn reject_counter(ctx: Context<RejectCounter>, deal_id: u64) -> Result<()> {
+        reject_counter::handler(ctx, deal_id)
+    }
+
+    pub fn set_taker_allowlist(ctx: Context<SetTakerAllowlist>, _deal_id: u64, allowlist: TakerAllowlist) -> Result<()> {
+        set_taker_allowlist::handler(ctx, allowlist)
+    }
+
+    pub fn take_signed_offer<'info>(ctx: Context<'_, '_, '_, 'info, TakeSignedOffer<'info>>, offer: SignedOffer) -> Result<()> {
+        take_signed_offer::handler(ctx, offer)
+    }
+
+    pub fn cancel_signed_offer(ctx: Context<CancelSignedOffer>, nonce: u64, expires_at: i64) -> Result<()> {
+        cancel_signed_offer::handler(ctx, nonce, expires_at)
+    }
+
+    pub fn close_offer_nonce(ctx: Context<CloseOfferNonce>, nonce: u64) -> Result<()> {
+        close_offer_nonce::handler(ctx, nonce)
+    }
+
+    pub fn init_order_index(ctx: Context<InitOrderIndex>, mint_a: Pubkey, mint_b: Pubkey, page: u32) -> Result<()> {
+        init_order_index::handler(ctx, mint_a, mint_b, page)
+    }
+
+    pub fn close<'info>(ctx: Context<'_, '_, '_, 'info, Close<'info>>, _deal_id: u64) -> Result<()>{
+        // mint_a still owed to takers has to be withdrawn first, everything else left in escrow is swept to the maker
+        require!(ctx.accounts.deal_details.maker_amt_owed == 0, ErrorCode::AccountContainsFund);
         close::handler(ctx)
     }
 }
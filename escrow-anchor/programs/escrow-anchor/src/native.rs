@@ -0,0 +1,189 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::program_pack::Pack;
+use anchor_lang::system_program::{allocate, assign, create_account, Allocate, Assign, CreateAccount};
+use anchor_spl::token::spl_token;
+use anchor_spl::token_2022::{close_account, spl_token_2022, sync_native, CloseAccount, SyncNative};
+use anchor_spl::token_interface::{initialize_account3, transfer_checked, InitializeAccount3, TokenAccount, TokenInterface, TransferChecked};
+
+// Helpers for legs paid in SOL. A native SOL leg is held as lamports in escrow_token_controller,
+// a wSOL leg is escrowed as tokens but funded from and paid out in plain lamports
+
+// escrow_token_controller keeps the rent exempt minimum while a deal holds native SOL,
+// otherwise a partial withdraw could leave it with a balance the runtime rejects
+pub(crate) fn controller_reserve() -> Result<u64> {
+    Ok(Rent::get()?.minimum_balance(0))
+}
+
+// Native SOL held by a deal, everything in escrow_token_controller above the reserve
+pub(crate) fn native_escrow_amount(controller: &AccountInfo) -> Result<u64> {
+    Ok(controller.lamports().saturating_sub(controller_reserve()?))
+}
+
+// Pays out lamports of a native SOL leg, the controller signs as it owns them
+pub(crate) fn transfer_from_controller<'info>(
+    controller: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+    amount: u64,
+) -> Result<()> {
+    let cpi_accounts = anchor_lang::system_program::Transfer {
+        from: controller.to_account_info(),
+        to: destination.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(
+        system_program.to_account_info(),
+        cpi_accounts,
+        signer_seeds,
+    );
+    anchor_lang::system_program::transfer(cpi_ctx, amount)
+}
+
+// Returns everything left in escrow_token_controller, reserve included, once the deal is closed
+pub(crate) fn sweep_controller<'info>(
+    controller: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    let leftover_funds = controller.lamports();
+    if leftover_funds > 0 {
+        transfer_from_controller(controller, destination, system_program, signer_seeds, leftover_funds)?;
+    }
+    Ok(())
+}
+
+// Moves a user's lamports into escrow_token_controller for a native SOL leg
+pub(crate) fn transfer_to_controller<'info>(
+    from: &AccountInfo<'info>,
+    controller: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    let cpi_accounts = anchor_lang::system_program::Transfer {
+        from: from.to_account_info(),
+        to: controller.to_account_info(),
+    };
+    anchor_lang::system_program::transfer(CpiContext::new(system_program.to_account_info(), cpi_accounts), amount)
+}
+
+// wSOL of either token program, deals in it are funded and paid out in plain lamports
+pub(crate) fn is_native_mint(mint: &Pubkey) -> bool {
+    *mint == spl_token::native_mint::ID || *mint == spl_token_2022::native_mint::ID
+}
+
+// Wraps a user's lamports straight into a wSOL escrow account, sync_native brings its token amount in line
+pub(crate) fn wrap_into_escrow<'info>(
+    from: &AccountInfo<'info>,
+    escrow_token_acc: &InterfaceAccount<'info, TokenAccount>,
+    token_program: &Interface<'info, TokenInterface>,
+    system_program: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    let cpi_accounts = anchor_lang::system_program::Transfer {
+        from: from.to_account_info(),
+        to: escrow_token_acc.to_account_info(),
+    };
+    anchor_lang::system_program::transfer(CpiContext::new(system_program.to_account_info(), cpi_accounts), amount)?;
+
+    let cpi_accounts = SyncNative {
+        account: escrow_token_acc.to_account_info(),
+    };
+    sync_native(CpiContext::new(token_program.to_account_info(), cpi_accounts))
+}
+
+// Accounts a wSOL payout is unwrapped through. The throwaway token account at [b"unwrap", deal, payer] is owned
+// by the controller while it exists and closed again in the same instruction
+pub(crate) struct UnwrapAccounts<'info> {
+    pub payer: AccountInfo<'info>,
+    pub unwrap_token_acc: AccountInfo<'info>,
+    pub mint: AccountInfo<'info>,
+    pub escrow_token_acc: AccountInfo<'info>,
+    pub controller: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+    pub system_program: AccountInfo<'info>,
+}
+
+// Pays wSOL out of an escrow as plain lamports. The payer fronts the rent of the throwaway account and gets it
+// back on close, the amount itself is passed on when someone else is being paid
+pub(crate) fn unwrap_to<'info>(
+    accounts: &UnwrapAccounts<'info>,
+    recipient: &AccountInfo<'info>,
+    deal: &Pubkey,
+    unwrap_bump: u8,
+    controller_seeds: &[&[&[u8]]],
+    amount: u64,
+) -> Result<()> {
+    let payer_key = accounts.payer.key();
+    let unwrap_seeds: &[&[&[u8]]] = &[&[b"unwrap", deal.as_ref(), payer_key.as_ref(), &[unwrap_bump]]];
+    let space = spl_token_2022::state::Account::LEN;
+    let rent = Rent::get()?.minimum_balance(space);
+
+    let current_lamports = accounts.unwrap_token_acc.lamports();
+    if current_lamports == 0 {
+        let cpi_accounts = CreateAccount {
+            from: accounts.payer.clone(),
+            to: accounts.unwrap_token_acc.clone(),
+        };
+        create_account(
+            CpiContext::new_with_signer(accounts.system_program.clone(), cpi_accounts, unwrap_seeds),
+            rent,
+            space as u64,
+            accounts.token_program.key,
+        )?;
+    } else {
+        // anyone can send lamports to the address beforehand, which create_account refuses,
+        // so it is topped up to rent exemption and allocated and assigned one step at a time
+        if current_lamports < rent {
+            let cpi_accounts = anchor_lang::system_program::Transfer {
+                from: accounts.payer.clone(),
+                to: accounts.unwrap_token_acc.clone(),
+            };
+            anchor_lang::system_program::transfer(CpiContext::new(accounts.system_program.clone(), cpi_accounts), rent - current_lamports)?;
+        }
+        let cpi_accounts = Allocate {
+            account_to_allocate: accounts.unwrap_token_acc.clone(),
+        };
+        allocate(CpiContext::new_with_signer(accounts.system_program.clone(), cpi_accounts, unwrap_seeds), space as u64)?;
+        let cpi_accounts = Assign {
+            account_to_assign: accounts.unwrap_token_acc.clone(),
+        };
+        assign(CpiContext::new_with_signer(accounts.system_program.clone(), cpi_accounts, unwrap_seeds), accounts.token_program.key)?;
+    }
+
+    let cpi_accounts = InitializeAccount3 {
+        account: accounts.unwrap_token_acc.clone(),
+        mint: accounts.mint.clone(),
+        authority: accounts.controller.clone(),
+    };
+    initialize_account3(CpiContext::new(accounts.token_program.clone(), cpi_accounts))?;
+
+    let cpi_accounts = TransferChecked {
+        mint: accounts.mint.clone(),
+        from: accounts.escrow_token_acc.clone(),
+        to: accounts.unwrap_token_acc.clone(),
+        authority: accounts.controller.clone(),
+    };
+    transfer_checked(
+        CpiContext::new(accounts.token_program.clone(), cpi_accounts).with_signer(controller_seeds),
+        amount,
+        spl_token::native_mint::DECIMALS,
+    )?;
+
+    let cpi_accounts = CloseAccount {
+        account: accounts.unwrap_token_acc.clone(),
+        destination: accounts.payer.clone(),
+        authority: accounts.controller.clone(),
+    };
+    close_account(CpiContext::new(accounts.token_program.clone(), cpi_accounts).with_signer(controller_seeds))?;
+
+    if recipient.key() != payer_key {
+        let cpi_accounts = anchor_lang::system_program::Transfer {
+            from: accounts.payer.clone(),
+            to: recipient.clone(),
+        };
+        anchor_lang::system_program::transfer(CpiContext::new(accounts.system_program.clone(), cpi_accounts), amount)?;
+    }
+    Ok(())
+}
+
@@ -0,0 +1,43 @@
+use anchor_lang::prelude::*;
+
+use crate::{ErrorCode, PriceCondition, MAX_PRICE_AGE};
+
+// Price accounts a deal can be conditioned on. After an 8 byte discriminator the account holds
+// the price and the unix timestamp it was published at, both little endian i64
+const PRICE_OFFSET: usize = 8;
+const PUBLISH_TIME_OFFSET: usize = 16;
+const PRICE_ACCOUNT_LEN: usize = 24;
+
+// price and publish time read from an oracle account
+pub(crate) fn read_price(oracle: &AccountInfo) -> Result<(i64, i64)> {
+    let data = oracle.try_borrow_data()?;
+    require!(data.len() >= PRICE_ACCOUNT_LEN, ErrorCode::InvalidOracle);
+
+    let read_i64 = |offset: usize| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&data[offset..offset + 8]);
+        i64::from_le_bytes(bytes)
+    };
+    Ok((read_i64(PRICE_OFFSET), read_i64(PUBLISH_TIME_OFFSET)))
+}
+
+// Fills of a price conditional deal only go through while a fresh oracle price is inside the range
+pub(crate) fn require_price_condition(condition: &Option<PriceCondition>, oracle: Option<&AccountInfo>) -> Result<()> {
+    let Some(condition) = condition else {
+        return Ok(());
+    };
+    let oracle = oracle.ok_or(ErrorCode::InvalidOracle)?;
+    require_keys_eq!(oracle.key(), condition.oracle, ErrorCode::InvalidOracle);
+
+    let (price, publish_time) = read_price(oracle)?;
+    let now = Clock::get()?.unix_timestamp;
+    // a publish time ahead of the clock would keep a price fresh long after MAX_PRICE_AGE
+    require!(publish_time <= now, ErrorCode::FuturePrice);
+    require!(now - publish_time <= MAX_PRICE_AGE, ErrorCode::StalePrice);
+    require!(
+        price >= condition.min_price && price <= condition.max_price,
+        ErrorCode::PriceOutOfRange
+    );
+    msg!("Oracle price {:?} within [{:?}, {:?}]", price, condition.min_price, condition.max_price);
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use anchor_lang::prelude::*;
+
+use crate::{DealDetails, ErrorCode, OrderIndex, MAX_INDEXED_DEALS};
+
+// Lists a freshly created deal on a page of the order index of its mint pair. The page is checked against its PDA here
+// rather than through seeds, a leg without a mint has no account to derive it from
+pub(crate) fn index_deal(
+    deal_details: &mut Account<DealDetails>,
+    order_index: &mut Account<OrderIndex>,
+    mint_a: &Pubkey,
+    mint_b: &Pubkey,
+) -> Result<()> {
+    let index_key = Pubkey::create_program_address(
+        &[b"index", mint_a.as_ref(), mint_b.as_ref(), &order_index.page.to_le_bytes(), &[order_index.order_index_bump]],
+        &crate::ID,
+    )
+    .map_err(|_| ErrorCode::InvalidOrderIndex)?;
+    require_keys_eq!(order_index.key(), index_key, ErrorCode::InvalidOrderIndex);
+    // a deal only one taker can fill has nothing to advertise
+    require!(deal_details.taker.is_none(), ErrorCode::IndexRequiresOpenOffer);
+    require!(order_index.deals.len() < MAX_INDEXED_DEALS as usize, ErrorCode::OrderIndexFull);
+
+    order_index.deals.push(deal_details.key());
+    deal_details.index_page = Some(order_index.page);
+    Ok(())
+}
+
+// Takes a deal off its order index page once it is no longer open, nothing to do for a deal that was never listed
+pub(crate) fn unindex_deal(deal_details: &mut Account<DealDetails>, order_index: &mut Option<Account<OrderIndex>>) -> Result<()> {
+    let Some(page) = deal_details.index_page else {
+        return Ok(());
+    };
+    let order_index = order_index.as_mut().ok_or(ErrorCode::MissingOrderIndex)?;
+    require!(order_index.page == page, ErrorCode::InvalidOrderIndex);
+    let deal_key = deal_details.key();
+    // index accounts are only ever created by this program, so the deal being listed in it is enough
+    let position = order_index
+        .deals
+        .iter()
+        .position(|deal| *deal == deal_key)
+        .ok_or(ErrorCode::InvalidOrderIndex)?;
+    order_index.deals.swap_remove(position);
+    deal_details.index_page = None;
+    Ok(())
+}
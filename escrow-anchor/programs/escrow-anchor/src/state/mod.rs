@@ -3,11 +3,81 @@ use anchor_lang::prelude::*;
 #[account]
 #[derive(InitSpace)]
 pub struct DealDetails{
+    // maker chosen id, lets a single maker keep several deals open at once
+    pub deal_id: u64,
     pub deal_details_bump: u8,
     pub escrow_token_controller_bump: u8,
     pub maker: Pubkey,
-    pub taker: Pubkey,
-    pub is_fullfilled : bool
+    // None for an open offer any taker can fill
+    pub taker: Option<Pubkey>,
+    pub state: DealState,
+    // set once a party has collected everything it is owed, stops a second withdraw after someone refills the escrow
+    pub maker_claimed: bool,
+    pub taker_claimed: bool,
+    // unix timestamp after which the deal can no longer be filled and anyone can refund it
+    pub expires_at: i64,
+    // what is still left to fill on each side, both reach zero together once the deal is fulfilled
+    pub maker_amt_remaining: u64,
+    pub taker_amt_remaining: u64,
+    // mint_a takers have filled for but not withdrawn yet
+    pub maker_amt_owed: u64,
+    // extra legs held in BasketDetails, 0 for a plain single mint deal
+    pub basket_legs: u8,
+    // settles disputes between maker and taker, None for a deal without one
+    pub arbiter: Option<Pubkey>,
+    // set by raise_dispute, freezes the deal until the arbiter resolves it
+    pub dispute_open: bool,
+    // deal only fills while the oracle price sits inside this range, None for an unconditional deal
+    pub price_condition: Option<PriceCondition>,
+    // takers an open offer is restricted to, None lets anyone fill it
+    pub taker_allowlist: Option<TakerAllowlist>,
+    // page of its mint pair's OrderIndex the deal is listed on, every instruction ending the deal has to take it off again
+    pub index_page: Option<u32>,
+    // an open offer that turns into a bilateral deal with whoever fills it first, the rest can only be filled by them
+    pub bind_first_taker: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace, Debug)]
+pub struct PriceCondition {
+    pub oracle: Pubkey,
+    // inclusive bounds, in the oracle's own units
+    pub min_price: i64,
+    pub max_price: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace, Debug)]
+pub enum TakerAllowlist {
+    // MAX_ALLOWED_TAKERS wallets at most
+    Takers {
+        #[max_len(8)]
+        takers: Vec<Pubkey>,
+    },
+    // root over keccak(taker) leaves, hashed in sorted pairs. Takers prove membership on each fill
+    MerkleRoot { root: [u8; 32] },
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace, Debug)]
+pub enum DealState {
+    Created,
+    PartiallyFilled,
+    // taker side has been filled completely, nobody withdrew yet
+    TakerDeposited,
+    MakerWithdrew,
+    TakerWithdrew,
+    // both parties collected everything, only close is left
+    Completed,
+    Cancelled,
+    Expired,
+}
+
+impl DealState {
+    // the whole deal has been filled, regardless of who withdrew since
+    pub fn is_filled(&self) -> bool {
+        matches!(
+            self,
+            DealState::TakerDeposited | DealState::MakerWithdrew | DealState::TakerWithdrew | DealState::Completed
+        )
+    }
 }
 
 
@@ -15,7 +85,129 @@ pub struct DealDetails{
 #[derive(InitSpace)]
 pub struct UserEscrowDetails {
     pub mint_amt : u64,
+    // Pubkey::default() for a native SOL leg, held as lamports in escrow_token_controller
     pub mint : Pubkey,
     pub escrow_token_acc_bump : u8,
     pub user_details_bump: u8,
-}
\ No newline at end of file
+    // maker side only, ordered tranches of mint_amt released one by one through approve_milestone.
+    // Empty when the whole amount is released on fill
+    #[max_len(8)]
+    pub milestones: Vec<u64>,
+    pub milestones_released: u8,
+    // sum of the approved tranches
+    pub released_amt: u64,
+}
+
+impl UserEscrowDetails {
+    pub fn is_native(&self) -> bool {
+        self.mint == Pubkey::default()
+    }
+
+    // mint_amt still locked behind milestones that have not been approved
+    pub fn remaining_amt(&self) -> u64 {
+        if self.milestones.is_empty() {
+            0
+        } else {
+            self.mint_amt - self.released_amt
+        }
+    }
+}
+
+// One per taker and deal, keeps track of the mint_a a taker earned through partial fills
+#[account]
+#[derive(InitSpace)]
+pub struct FillDetails {
+    pub taker: Pubkey,
+    pub maker_amt_owed: u64,
+    pub fill_details_bump: u8,
+}
+
+// Extra legs of a basket deal, on top of the mint_a / mint_b pair in the user details.
+// Each leg is escrowed in its own token account seeded with its index
+#[account]
+#[derive(InitSpace)]
+pub struct BasketDetails {
+    // MAX_BASKET_LEGS on either side
+    #[max_len(6)]
+    pub legs: Vec<BasketLeg>,
+    pub basket_details_bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct BasketLeg {
+    pub side: LegSide,
+    pub mint: Pubkey,
+    pub mint_amt: u64,
+    pub escrow_token_acc_bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace, Debug)]
+pub enum LegSide {
+    // funded by the maker when the leg is added, paid out to the taker
+    Maker,
+    // funded by the taker on deposit, paid out to the maker
+    Taker,
+}
+
+impl BasketDetails {
+    pub fn side_count(&self, side: LegSide) -> usize {
+        self.legs.iter().filter(|leg| leg.side == side).count()
+    }
+}
+
+// A taker's counter to the deal's terms, one per taker and deal until the maker accepts or rejects it
+#[account]
+#[derive(InitSpace)]
+pub struct Proposal {
+    pub taker: Pubkey,
+    pub maker_amt: u64,
+    pub taker_amt: u64,
+    pub proposal_bump: u8,
+}
+
+// Terms a maker signs off-chain, the signed message is the borsh serialized struct behind SIGNED_OFFER_DOMAIN
+// and the program id. Settled in one go by take_signed_offer, nothing is escrowed before then
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct SignedOffer {
+    pub maker: Pubkey,
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+    pub maker_amt: u64,
+    pub taker_amt: u64,
+    pub expires_at: i64,
+    // picked by the maker, each (maker, nonce) settles or is cancelled once
+    pub nonce: u64,
+}
+
+impl SignedOffer {
+    pub fn message(&self) -> Result<Vec<u8>> {
+        Ok([crate::SIGNED_OFFER_DOMAIN, crate::ID.as_ref(), &self.try_to_vec()?].concat())
+    }
+}
+
+// Marks a signed offer nonce as used, the account existing is all that matters until the offer expires.
+// After that the offer can't be taken anyway and close_offer_nonce hands the rent back
+#[account]
+#[derive(InitSpace)]
+pub struct OfferNonce {
+    pub offer_nonce_bump: u8,
+    // whoever paid the rent, the taker or the maker who cancelled
+    pub payer: Pubkey,
+    pub expires_at: i64,
+}
+
+// One page of open offers of a (mint_a, mint_b) pair, lets clients list a pair's order book without scanning every deal.
+// Deals are added by create and removed once taken, cancelled, expired or closed. A pair has as many pages as anyone
+// cares to create, so filling one up never keeps other makers from listing
+#[account]
+#[derive(InitSpace)]
+pub struct OrderIndex {
+    // Pubkey::default() for a native SOL leg, like in UserEscrowDetails
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+    pub page: u32,
+    // MAX_INDEXED_DEALS
+    #[max_len(64)]
+    pub deals: Vec<Pubkey>,
+    pub order_index_bump: u8,
+}
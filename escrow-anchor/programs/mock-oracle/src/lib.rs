@@ -0,0 +1,56 @@
+#![allow(unexpected_cfgs)]
+#![allow(deprecated)]
+
+use anchor_lang::prelude::*;
+
+declare_id!("GZsvz4beFEzLraueUpSRZ4sFe59irrbw6ecKbo2hVVKL");
+
+// Stand-in for a real price oracle, whoever owns a feed can set any price on it.
+// Only exists so the escrow tests can move the price around a deal's condition
+#[program]
+pub mod mock_oracle {
+    use super::*;
+
+    pub fn set_price(ctx: Context<SetPrice>, price: i64) -> Result<()> {
+        let price_feed = &mut ctx.accounts.price_feed;
+        price_feed.price = price;
+        price_feed.publish_time = Clock::get()?.unix_timestamp;
+        msg!("Price set to {:?}", price);
+        Ok(())
+    }
+
+    // lets the tests publish a price with any timestamp, including one ahead of the clock
+    pub fn set_price_at(ctx: Context<SetPrice>, price: i64, publish_time: i64) -> Result<()> {
+        let price_feed = &mut ctx.accounts.price_feed;
+        price_feed.price = price;
+        price_feed.publish_time = publish_time;
+        msg!("Price set to {:?} at {:?}", price, publish_time);
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct SetPrice<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // one feed per authority
+    #[account(
+        init_if_needed,
+        payer=authority,
+        seeds=[b"price", authority.key().as_ref()],
+        space=8+PriceFeed::INIT_SPACE,
+        bump
+    )]
+    pub price_feed: Account<'info, PriceFeed>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// Layout the escrow reads, 8 byte discriminator followed by both fields as little endian i64
+#[account]
+#[derive(InitSpace)]
+pub struct PriceFeed {
+    pub price: i64,
+    pub publish_time: i64,
+}
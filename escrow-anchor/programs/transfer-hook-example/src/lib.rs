@@ -0,0 +1,94 @@
+#![allow(unexpected_cfgs)]
+#![allow(deprecated)]
+
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount};
+use spl_discriminator::SplDiscriminate;
+use spl_tlv_account_resolution::{account::ExtraAccountMeta, seeds::Seed, state::ExtraAccountMetaList};
+use spl_transfer_hook_interface::instruction::ExecuteInstruction;
+
+declare_id!("4Wrpra2KKSETi4zCqy1nbmen5ZyD6GJqFbvtq1Q12wkv");
+
+// Smallest useful transfer hook, counts every transfer of the mint in a PDA that token-2022 has to be handed
+// as an extra account. Only exists so the escrow tests can move a hooked mint
+#[program]
+pub mod transfer_hook_example {
+    use super::*;
+
+    pub fn initialize_extra_account_meta_list(ctx: Context<InitializeExtraAccountMetaList>) -> Result<()> {
+        ExtraAccountMetaList::init::<ExecuteInstruction>(
+            &mut ctx.accounts.extra_account_meta_list.try_borrow_mut_data()?,
+            &extra_account_metas()?,
+        )?;
+        ctx.accounts.counter.bump = ctx.bumps.counter;
+        Ok(())
+    }
+
+    #[instruction(discriminator = ExecuteInstruction::SPL_DISCRIMINATOR_SLICE)]
+    pub fn transfer_hook(ctx: Context<TransferHook>, _amount: u64) -> Result<()> {
+        ctx.accounts.counter.transfers += 1;
+        msg!("Transfer number {:?}", ctx.accounts.counter.transfers);
+        Ok(())
+    }
+}
+
+// the counter PDA is the only extra account every transfer needs
+fn extra_account_metas() -> Result<Vec<ExtraAccountMeta>> {
+    Ok(vec![ExtraAccountMeta::new_with_seeds(
+        &[Seed::Literal { bytes: b"counter".to_vec() }],
+        false,
+        true,
+    )?])
+}
+
+#[derive(Accounts)]
+pub struct InitializeExtraAccountMetaList<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: laid out by ExtraAccountMetaList::init, token-2022 reads it to resolve the counter
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"extra-account-metas", mint.key().as_ref()],
+        space = ExtraAccountMetaList::size_of(extra_account_metas()?.len())?,
+        bump
+    )]
+    pub extra_account_meta_list: UncheckedAccount<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(init, payer = payer, seeds = [b"counter"], space = 8 + TransferCounter::INIT_SPACE, bump)]
+    pub counter: Account<'info, TransferCounter>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// account order is fixed by the transfer hook interface
+#[derive(Accounts)]
+pub struct TransferHook<'info> {
+    #[account(token::mint = mint)]
+    pub source_token: InterfaceAccount<'info, TokenAccount>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(token::mint = mint)]
+    pub destination_token: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: owner or delegate of the source account, not needed by this hook
+    pub owner: UncheckedAccount<'info>,
+
+    /// CHECK: extra-account-metas PDA of the mint
+    #[account(seeds = [b"extra-account-metas", mint.key().as_ref()], bump)]
+    pub extra_account_meta_list: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"counter"], bump = counter.bump)]
+    pub counter: Account<'info, TransferCounter>,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct TransferCounter {
+    pub transfers: u64,
+    pub bump: u8,
+}
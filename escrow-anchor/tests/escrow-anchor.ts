@@ -1,15 +1,32 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { EscrowAnchor } from "../target/types/escrow_anchor";
+import { TransferHookExample } from "../target/types/transfer_hook_example";
+import { MockOracle } from "../target/types/mock_oracle";
+import { fetchOpenDeals, orderIndexPda } from "../app/order-index";
 import {
   createMint,
   mintTo,
   createAccount,
   TOKEN_2022_PROGRAM_ID,
+  TOKEN_PROGRAM_ID,
   getAssociatedTokenAddressSync,
   createAssociatedTokenAccountIdempotentInstruction,
   getOrCreateAssociatedTokenAccount,
+  createNativeMint,
+  NATIVE_MINT_2022,
+  ExtensionType,
+  getMintLen,
+  createInitializeTransferFeeConfigInstruction,
+  createInitializeMintInstruction,
+  createInitializeTransferHookInstruction,
+  createInitializeNonTransferableMintInstruction,
+  createInitializePermanentDelegateInstruction,
+  createInitializeDefaultAccountStateInstruction,
+  AccountState,
+  approveChecked,
 } from "@solana/spl-token";
+import { keccak_256 } from "@noble/hashes/sha3";
 import { expect } from "chai";
 
 async function logAddressBalance(
@@ -22,6 +39,21 @@ async function logAddressBalance(
   return lamports;
 }
 
+// Error a transaction is rejected with, an unexpected success fails the test
+async function rejection(tx: Promise<unknown>): Promise<unknown> {
+  try {
+    await tx;
+  } catch (err) {
+    return err;
+  }
+  expect.fail("transaction was expected to fail");
+}
+
+// Fails unless the transaction is rejected with the given Anchor error code
+async function expectError(tx: Promise<unknown>, code: string) {
+  expect(((await rejection(tx)) as anchor.AnchorError).error?.errorCode.code).eq(code);
+}
+
 describe("escrow-anchor", () => {
   // Configure the client to use the local cluster.
   const provider = anchor.AnchorProvider.env();
@@ -31,11 +63,46 @@ describe("escrow-anchor", () => {
   const tokenMaker = provider.wallet;
   let ataMakerMintA: undefined | anchor.web3.PublicKey; // Can change its type to store ataMakerMintA details
   let ataTakerMintB: undefined | anchor.web3.PublicKey; // Can change its type to store ataTakerMintB details
+  let ataSecondMakerMintA: undefined | anchor.web3.PublicKey;
 
-  const [maker, taker, mintA, mintB] = Array.from({ length: 4 }, () => {
+  const [maker, taker, mintA, mintB, secondMaker] = Array.from({ length: 5 }, () => {
     return anchor.web3.Keypair.generate();
   });
 
+  // Every deal PDA is seeded with the maker chosen deal id
+  const dealId = new anchor.BN(1);
+  const dealIdSeed = (id: anchor.BN) => id.toArrayLike(Buffer, "le", 8);
+  const inAnHour = () => new anchor.BN(Math.floor(Date.now() / 1000) + 3600);
+  const dealPda = (id: anchor.BN, dealMaker = maker.publicKey) =>
+    anchor.web3.PublicKey.findProgramAddressSync([Buffer.from("deal"), dealMaker.toBuffer(), dealIdSeed(id)], program.programId)[0];
+  const ata = (mint: anchor.web3.PublicKey, owner: anchor.web3.PublicKey, tokenProgram = TOKEN_2022_PROGRAM_ID) =>
+    getAssociatedTokenAddressSync(mint, owner, false, tokenProgram);
+  // Creates the owner's associated token account for mint if it is missing and mints amount into it
+  const createAta = async (mint: anchor.web3.PublicKey, owner: anchor.web3.PublicKey, amount = 0, tokenProgram = TOKEN_2022_PROGRAM_ID) => {
+    const { address } = await getOrCreateAssociatedTokenAccount(provider.connection, tokenMaker.payer, mint, owner, undefined, undefined, undefined, tokenProgram);
+    if (amount > 0) {
+      await mintTo(provider.connection, tokenMaker.payer, mint, address, tokenMaker.publicKey, amount, [], undefined, tokenProgram);
+    }
+    return address;
+  };
+
+  // Unix time on the cluster clock the program checks expiries against
+  const clusterTime = async () => (await provider.connection.getBlockTime(await provider.connection.getSlot()))!;
+  // Polls the cluster clock until it has moved past the given unix timestamp
+  const waitUntilPast = async (timestamp: number) => {
+    while ((await clusterTime()) <= timestamp) {
+      await new Promise((resolve) => setTimeout(resolve, 400));
+    }
+  };
+
+  // Mints the maker the mint_a and the taker the mint_b a describe block's deals are about to trade
+  const fundParties = async (makerAmt: number, takerAmt: number) => {
+    await mintTo(provider.connection, tokenMaker.payer, mintA.publicKey, ataMakerMintA, tokenMaker.publicKey, makerAmt, [], undefined, TOKEN_2022_PROGRAM_ID);
+    if (takerAmt > 0) {
+      await mintTo(provider.connection, tokenMaker.payer, mintB.publicKey, ataTakerMintB, tokenMaker.publicKey, takerAmt, [], undefined, TOKEN_2022_PROGRAM_ID);
+    }
+  };
+
   let escrowTokenController: anchor.web3.PublicKey;
   let escrowTokenAccA: anchor.web3.PublicKey;
   let escrowTokenAccB: anchor.web3.PublicKey;
@@ -50,7 +117,7 @@ describe("escrow-anchor", () => {
   // Mint some amount of mint_a token to maker and mint_b token to taker
   before(async () => {
     // Airdrop SOL to maker
-    [maker,taker].forEach(async user => {
+    await Promise.all([maker, taker, secondMaker].map(async user => {
         const airDropTx = await provider.connection.requestAirdrop(
         user.publicKey,
         100 * anchor.web3.LAMPORTS_PER_SOL
@@ -65,7 +132,7 @@ describe("escrow-anchor", () => {
         "confirmed",
       );
       await logAddressBalance(user.publicKey, "User SOL Balance:", provider);
-    })
+    }));
 
     await createMint(
       provider.connection,
@@ -114,6 +181,16 @@ describe("escrow-anchor", () => {
       TOKEN_2022_PROGRAM_ID
     );
 
+    ataSecondMakerMintA = await createAccount(
+      provider.connection,
+      tokenMaker.payer,
+      mintA.publicKey,
+      secondMaker.publicKey,
+      undefined,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+
     console.log("Maker's ATA for Mint A:", ataMakerMintA.toBase58());
     console.log("Taker's ATA for Mint B:", ataTakerMintB.toBase58());
 
@@ -141,6 +218,18 @@ describe("escrow-anchor", () => {
       TOKEN_2022_PROGRAM_ID
     );
 
+    await mintTo(
+      provider.connection,
+      tokenMaker.payer,
+      mintA.publicKey,
+      ataSecondMakerMintA,
+      tokenMaker.publicKey,
+      1000,
+      [],
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+
     const makerAmount = await provider.connection.getTokenAccountBalance(
       ataMakerMintA,
       "confirmed"
@@ -153,33 +242,30 @@ describe("escrow-anchor", () => {
     console.log("Checking ATA Taker : ", takerAmount.value.uiAmountString);
 
     // Calculate PDAs once to use in tests
-    [dealDetailsPda] = anchor.web3.PublicKey.findProgramAddressSync(
-      [Buffer.from("deal"), maker.publicKey.toBuffer()],
-      program.programId
-    );
+    dealDetailsPda = dealPda(dealId);
 
     [escrowTokenController] = anchor.web3.PublicKey.findProgramAddressSync(
-      [Buffer.from("controller"), maker.publicKey.toBuffer()],
+      [Buffer.from("controller"), maker.publicKey.toBuffer(), dealIdSeed(dealId)],
       program.programId
     );
 
     [escrowTokenAccA] = anchor.web3.PublicKey.findProgramAddressSync(
-      [Buffer.from("token"), maker.publicKey.toBuffer()],
+      [Buffer.from("token_a"), dealDetailsPda.toBuffer()],
       program.programId
     );
 
     [escrowTokenAccB] = anchor.web3.PublicKey.findProgramAddressSync(
-      [Buffer.from("token"), taker.publicKey.toBuffer()],
+      [Buffer.from("token_b"), dealDetailsPda.toBuffer()],
       program.programId
     );
 
     [userADetailsPda] = anchor.web3.PublicKey.findProgramAddressSync(
-      [Buffer.from("user_details"), maker.publicKey.toBuffer()],
+      [Buffer.from("user_a_details"), dealDetailsPda.toBuffer()],
       program.programId
     );
 
     [userBDetailsPda] = anchor.web3.PublicKey.findProgramAddressSync(
-      [Buffer.from("user_details"), taker.publicKey.toBuffer()],
+      [Buffer.from("user_b_details"), dealDetailsPda.toBuffer()],
       program.programId
     );
 
@@ -207,13 +293,14 @@ describe("escrow-anchor", () => {
     );
 
     const tx = await program.methods
-      .create(maker_amt, taker_amt)
+      .create(dealId, maker_amt, taker_amt, inAnHour(), false)
       .accounts({
         maker: maker.publicKey,
         taker: taker.publicKey,
         mintA: mintA.publicKey,
         mintB: mintB.publicKey,
-        tokenProgram: TOKEN_2022_PROGRAM_ID, // Use the correct program ID
+        tokenProgramA: TOKEN_2022_PROGRAM_ID,
+        tokenProgramB: TOKEN_2022_PROGRAM_ID,
       })
       .signers([maker]) // The 'maker' is the signer for this instruction
       .rpc();
@@ -226,7 +313,7 @@ describe("escrow-anchor", () => {
     );
     expect(dealDetailsAccount.maker.equals(maker.publicKey)).to.be.true;
     expect(dealDetailsAccount.taker.equals(taker.publicKey)).to.be.true;
-    expect(dealDetailsAccount.isFullfilled).to.be.false;
+    expect(dealDetailsAccount.state).to.deep.equal({ created: {} });
 
     // 2. Verify UserADetails account was created and data is correct
     const userADetailsAccount = await program.account.userEscrowDetails.fetch(
@@ -265,14 +352,51 @@ describe("escrow-anchor", () => {
     expect(escrowATABalance.value.uiAmount).eq(1);
   });
 
+  it("Maker can keep a second deal open alongside the first", async () => {
+    const secondDealId = new anchor.BN(2);
+
+    await program.methods
+      .create(secondDealId, new anchor.BN(500), new anchor.BN(200), inAnHour(), false)
+      .accounts({
+        maker: maker.publicKey,
+        taker: taker.publicKey,
+        mintA: mintA.publicKey,
+        mintB: mintB.publicKey,
+        tokenProgramA: TOKEN_2022_PROGRAM_ID,
+        tokenProgramB: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([maker])
+      .rpc();
+
+    const secondDealPda = dealPda(secondDealId);
+    const [secondEscrowTokenAccA] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("token_a"), secondDealPda.toBuffer()],
+      program.programId
+    );
+
+    const secondDeal = await program.account.dealDetails.fetch(secondDealPda);
+    expect(secondDeal.dealId.eq(secondDealId)).to.be.true;
+
+    // First deal is left untouched by the second one
+    const firstDeal = await program.account.dealDetails.fetch(dealDetailsPda);
+    expect(firstDeal.dealId.eq(dealId)).to.be.true;
+    expect(firstDeal.state).to.deep.equal({ created: {} });
+
+    const secondEscrowBalance = await provider.connection.getTokenAccountBalance(
+      secondEscrowTokenAccA,
+      "confirmed"
+    );
+    expect(secondEscrowBalance.value.amount).eq("500");
+  });
+
   it("Deposit amount to existing deal", async () => {
     await program.methods
-      .deposit()
+      .deposit(dealId, new anchor.BN(1500), new anchor.BN(1000), new anchor.BN(1500), [])
       .accounts({
         maker: maker.publicKey,
         taker: taker.publicKey,
         mint: mintB.publicKey,
-        tokenProgram: TOKEN_2022_PROGRAM_ID,
+        tokenProgramB: TOKEN_2022_PROGRAM_ID,
       })
       .signers([taker])
       .rpc();
@@ -280,11 +404,294 @@ describe("escrow-anchor", () => {
     // const takerATAamount = await provider.connection.getTokenAccountBalance(escrowTokenAccB, 'confirmed');
     // expect(takerATAamount.value.uiAmount).eq(15);
 
-    // Check if deal_details.is_fullfilled is true
+    // Check if the deal moved to the taker deposited state
     const dealDetails = await program.account.dealDetails.fetch(dealDetailsPda);
-    expect(dealDetails.isFullfilled).eq(true);
+    expect(dealDetails.state).to.deep.equal({ takerDeposited: {} });
+  });
+
+  describe("Two makers trading with the same taker", () => {
+    // The second maker deliberately reuses the same deal id as the first one
+    let secondMakerDealPda: anchor.web3.PublicKey;
+    let secondMakerEscrowTokenAccB: anchor.web3.PublicKey;
+    let secondMakerUserBDetailsPda: anchor.web3.PublicKey;
+
+    before(async () => {
+      secondMakerDealPda = dealPda(dealId, secondMaker.publicKey);
+      [secondMakerEscrowTokenAccB] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("token_b"), secondMakerDealPda.toBuffer()],
+        program.programId
+      );
+      [secondMakerUserBDetailsPda] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("user_b_details"), secondMakerDealPda.toBuffer()],
+        program.programId
+      );
+    });
+
+    it("Second maker can open a deal with a taker who is already in a deal", async () => {
+      await program.methods
+        .create(dealId, new anchor.BN(400), new anchor.BN(300), inAnHour(), false)
+        .accounts({
+          maker: secondMaker.publicKey,
+          taker: taker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([secondMaker])
+        .rpc();
+
+      // Escrow accounts of the taker side are scoped to each deal
+      expect(secondMakerEscrowTokenAccB.equals(escrowTokenAccB)).to.be.false;
+      expect(secondMakerUserBDetailsPda.equals(userBDetailsPda)).to.be.false;
+
+      const secondMakerDeal = await program.account.dealDetails.fetch(secondMakerDealPda);
+      expect(secondMakerDeal.maker.equals(secondMaker.publicKey)).to.be.true;
+      expect(secondMakerDeal.taker.equals(taker.publicKey)).to.be.true;
+    });
+
+    it("Rejects a deposit routed into another deal's escrow account", async () => {
+      await expectError(
+        program.methods
+          .deposit(dealId, new anchor.BN(300), new anchor.BN(400), new anchor.BN(300), [])
+          .accountsPartial({
+            maker: secondMaker.publicKey,
+            taker: taker.publicKey,
+            mint: mintB.publicKey,
+            escrowTokenAccB: escrowTokenAccB,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([taker])
+          .rpc(),
+        "ConstraintSeeds"
+      );
+    });
+
+    it("Rejects another deal's taker details", async () => {
+      await expectError(
+        program.methods
+          .deposit(dealId, new anchor.BN(300), new anchor.BN(400), new anchor.BN(300), [])
+          .accountsPartial({
+            maker: secondMaker.publicKey,
+            taker: taker.publicKey,
+            mint: mintB.publicKey,
+            userBDetails: userBDetailsPda,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([taker])
+          .rpc(),
+        "ConstraintSeeds"
+      );
+    });
+
+    it("Rejects a signer who is not the deal's taker", async () => {
+      await expectError(
+        program.methods
+          .deposit(dealId, new anchor.BN(300), new anchor.BN(400), new anchor.BN(300), [])
+          .accountsPartial({
+            maker: secondMaker.publicKey,
+            taker: maker.publicKey,
+            userTokenAccB: ataTakerMintB,
+            mint: mintB.publicKey,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([maker])
+          .rpc(),
+        "InvalidUser"
+      );
+    });
+
+    it("Taker fills the second maker's deal while the first one is still open", async () => {
+      await program.methods
+        .deposit(dealId, new anchor.BN(300), new anchor.BN(400), new anchor.BN(300), [])
+        .accounts({
+          maker: secondMaker.publicKey,
+          taker: taker.publicKey,
+          mint: mintB.publicKey,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([taker])
+        .rpc();
+
+      const secondMakerDeal = await program.account.dealDetails.fetch(secondMakerDealPda);
+      expect(secondMakerDeal.state).to.deep.equal({ takerDeposited: {} });
+
+      // Each deal keeps its own taker balance
+      const firstEscrowB = await provider.connection.getTokenAccountBalance(escrowTokenAccB, "confirmed");
+      const secondEscrowB = await provider.connection.getTokenAccountBalance(secondMakerEscrowTokenAccB, "confirmed");
+      expect(firstEscrowB.value.amount).eq("1500");
+      expect(secondEscrowB.value.amount).eq("300");
+    });
   });
 
+  describe("Open offer", () => {
+    const openDealId = new anchor.BN(3);
+    let openDealPda: anchor.web3.PublicKey;
+
+    before(async () => {
+      openDealPda = dealPda(openDealId);
+    });
+
+    it("Maker posts an offer without a taker", async () => {
+      await program.methods
+        .create(openDealId, new anchor.BN(300), new anchor.BN(100), inAnHour(), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker: null,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+
+      const openDeal = await program.account.dealDetails.fetch(openDealPda);
+      expect(openDeal.taker).to.be.null;
+      expect(openDeal.state).to.deep.equal({ created: {} });
+    });
+
+    it("Taker partially fills the offer and withdraws their share right away", async () => {
+      await program.methods
+        .deposit(openDealId, new anchor.BN(60), new anchor.BN(300), new anchor.BN(100), [])
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mint: mintB.publicKey,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([taker])
+        .rpc();
+
+      let openDeal = await program.account.dealDetails.fetch(openDealPda);
+      expect(openDeal.taker).to.be.null;
+      expect(openDeal.state).to.deep.equal({ partiallyFilled: {} });
+      expect(openDeal.takerAmtRemaining.toNumber()).eq(40);
+      expect(openDeal.makerAmtRemaining.toNumber()).eq(120);
+
+      const [takerFillPda] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("fill"), openDealPda.toBuffer(), taker.publicKey.toBuffer()],
+        program.programId
+      );
+      const takerFill = await program.account.fillDetails.fetch(takerFillPda);
+      expect(takerFill.makerAmtOwed.toNumber()).eq(180);
+
+      // separate account so the taker's ATA balance stays untouched for the withdraw test below
+      const takerPayoutAcc = await createAccount(
+        provider.connection,
+        tokenMaker.payer,
+        mintA.publicKey,
+        taker.publicKey,
+        anchor.web3.Keypair.generate(),
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      await program.methods
+        .withdraw(openDealId)
+        .accounts({
+          maker: maker.publicKey,
+          signer: taker.publicKey,
+          userTokenAcc: takerPayoutAcc,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          mintExchange: mintA.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          fillDetails: takerFillPda,
+        })
+        .signers([taker])
+        .rpc();
+
+      const payout = await provider.connection.getTokenAccountBalance(takerPayoutAcc);
+      expect(payout.value.amount).eq("180");
+      expect(await program.account.fillDetails.fetchNullable(takerFillPda)).to.be.null;
+
+      openDeal = await program.account.dealDetails.fetch(openDealPda);
+      expect(openDeal.takerAmtRemaining.toNumber()).eq(40);
+    });
+
+    it("Second taker fills the rest of the offer", async () => {
+      const secondTakerMintB = await getOrCreateAssociatedTokenAccount(
+        provider.connection,
+        secondMaker,
+        mintB.publicKey,
+        secondMaker.publicKey,
+        undefined,
+        undefined,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      await mintTo(
+        provider.connection,
+        tokenMaker.payer,
+        mintB.publicKey,
+        secondTakerMintB.address,
+        tokenMaker.publicKey,
+        100,
+        [],
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      await expectError(
+        program.methods
+          .deposit(openDealId, new anchor.BN(41), new anchor.BN(300), new anchor.BN(100), [])
+          .accounts({
+            maker: maker.publicKey,
+            taker: secondMaker.publicKey,
+            mint: mintB.publicKey,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([secondMaker])
+          .rpc(),
+        "InvalidFillAmount"
+      );
+
+      await program.methods
+        .deposit(openDealId, new anchor.BN(40), new anchor.BN(300), new anchor.BN(100), [])
+        .accounts({
+          maker: maker.publicKey,
+          taker: secondMaker.publicKey,
+          mint: mintB.publicKey,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([secondMaker])
+        .rpc();
+
+      const openDeal = await program.account.dealDetails.fetch(openDealPda);
+      expect(openDeal.state).to.deep.equal({ takerDeposited: {} });
+      expect(openDeal.takerAmtRemaining.toNumber()).eq(0);
+      expect(openDeal.makerAmtRemaining.toNumber()).eq(0);
+
+      const [secondTakerFillPda] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("fill"), openDealPda.toBuffer(), secondMaker.publicKey.toBuffer()],
+        program.programId
+      );
+      const secondTakerFill = await program.account.fillDetails.fetch(secondTakerFillPda);
+      expect(secondTakerFill.makerAmtOwed.toNumber()).eq(120);
+    });
+
+    it("Rejects fills once the offer is complete", async () => {
+      await expectError(
+        program.methods
+          .deposit(openDealId, new anchor.BN(10), new anchor.BN(300), new anchor.BN(100), [])
+          .accounts({
+            maker: maker.publicKey,
+            taker: secondMaker.publicKey,
+            mint: mintB.publicKey,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([secondMaker])
+          .rpc(),
+        "DealAlreadyFulfilled"
+      );
+    });
+  });
+
+  let makerLamportsBeforeTakerWithdraw: number;
+  let makerLamportsAfterTakerWithdraw: number;
+
   it("Withdraw funds from escrow accounts", async () => {
     // create a new token account for the maker and taker
     // hit the withdraw instruction with maker or taker ids
@@ -307,16 +714,17 @@ describe("escrow-anchor", () => {
     expect(makerMintBataAmount.value.uiAmount).eq(0);
 
     const makerWithdrawIx = await program.methods
-      .withdraw()
+      .withdraw(dealId)
       .accounts({
         maker: maker.publicKey,
-        taker: taker.publicKey,
         signer: maker.publicKey,
         userTokenAcc: makerMintBata.address,
         mintA: mintA.publicKey,
         mintB: mintB.publicKey,
         mintExchange: mintB.publicKey,
-        tokenProgram: TOKEN_2022_PROGRAM_ID,
+        tokenProgramA: TOKEN_2022_PROGRAM_ID,
+        tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        fillDetails: null,
       })
       .signers([maker])
       .rpc();
@@ -339,18 +747,25 @@ describe("escrow-anchor", () => {
 
 
     let takerMintAataAmount = await provider.connection.getTokenAccountBalance(takerMintAata.address);
+    const [takerFillPda] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("fill"), dealDetailsPda.toBuffer(), taker.publicKey.toBuffer()],
+      program.programId
+    );
+
+    makerLamportsBeforeTakerWithdraw = await provider.connection.getBalance(maker.publicKey);
 
     const takerWithdrawIx = await program.methods
-      .withdraw()
+      .withdraw(dealId)
       .accounts({
         maker: maker.publicKey,
-        taker: taker.publicKey,
         signer: taker.publicKey,
         userTokenAcc: takerMintAata.address,
         mintA: mintA.publicKey,
         mintB: mintB.publicKey,
         mintExchange: mintA.publicKey,
-        tokenProgram: TOKEN_2022_PROGRAM_ID,
+        tokenProgramA: TOKEN_2022_PROGRAM_ID,
+        tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        fillDetails: takerFillPda,
       })
       .signers([taker])
       .rpc();
@@ -359,24 +774,2657 @@ describe("escrow-anchor", () => {
 
     takerMintAataAmount = await provider.connection.getTokenAccountBalance(takerMintAata.address);
     expect(takerMintAataAmount.value.uiAmount).eq(1);
+
+    makerLamportsAfterTakerWithdraw = await provider.connection.getBalance(maker.publicKey);
     
   });
 
-  it("Close all accounts and transfer funds to maker ", async () => {
-    let beforeMakerBalance = await provider.connection.getBalance(maker.publicKey);
-    console.log("Makers balance before : ", beforeMakerBalance);
+  it("Last withdraw closes all accounts and refunds rent to maker", async () => {
+    expect(makerLamportsAfterTakerWithdraw).greaterThan(makerLamportsBeforeTakerWithdraw);
+
+    expect(await program.account.dealDetails.fetchNullable(dealDetailsPda)).to.be.null;
+    expect(await program.account.userEscrowDetails.fetchNullable(userADetailsPda)).to.be.null;
+    expect(await program.account.userEscrowDetails.fetchNullable(userBDetailsPda)).to.be.null;
+    expect(await provider.connection.getAccountInfo(escrowTokenAccA)).to.be.null;
+    expect(await provider.connection.getAccountInfo(escrowTokenAccB)).to.be.null;
+  });
+
+  describe("Take", () => {
+    const takeDealId = new anchor.BN(4);
+    let takeDealPda: anchor.web3.PublicKey;
+    let takeEscrowTokenAccA: anchor.web3.PublicKey;
+    let takeEscrowTokenAccB: anchor.web3.PublicKey;
+
+    before(async () => {
+      takeDealPda = dealPda(takeDealId);
+      [takeEscrowTokenAccA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("token_a"), takeDealPda.toBuffer()],
+        program.programId
+      );
+      [takeEscrowTokenAccB] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("token_b"), takeDealPda.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .create(takeDealId, new anchor.BN(200), new anchor.BN(100), inAnHour(), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+    });
+
+    it("Rejects paying a token leg in lamports", async () => {
+      // without mint_b the payment would otherwise be taken as a native SOL leg
+      await expectError(
+        program.methods
+          .take(takeDealId, new anchor.BN(200), new anchor.BN(100), [])
+          .accounts({
+            taker: taker.publicKey,
+            maker: maker.publicKey,
+            mintB: null,
+            takerTokenAccB: null,
+            makerTokenAccB: null,
+            tokenProgramA: TOKEN_2022_PROGRAM_ID,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([taker])
+          .rpc(),
+        "InvalidMint"
+      );
+    });
+
+    it("Settles the deal and closes the escrow in one instruction", async () => {
+      const makerMintBata = ata(mintB.publicKey, maker.publicKey);
+      const takerMintAata = ata(mintA.publicKey, taker.publicKey);
+      const makerMintBBefore = await provider.connection.getTokenAccountBalance(makerMintBata);
+      const takerMintABefore = await provider.connection.getTokenAccountBalance(takerMintAata);
+
+      await program.methods
+        .take(takeDealId, new anchor.BN(200), new anchor.BN(100), [])
+        .accounts({
+          taker: taker.publicKey,
+          maker: maker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([taker])
+        .rpc();
+
+      const makerMintBAfter = await provider.connection.getTokenAccountBalance(makerMintBata);
+      const takerMintAAfter = await provider.connection.getTokenAccountBalance(takerMintAata);
+      expect(Number(makerMintBAfter.value.amount) - Number(makerMintBBefore.value.amount)).eq(100);
+      expect(Number(takerMintAAfter.value.amount) - Number(takerMintABefore.value.amount)).eq(200);
+
+      // Nothing is left behind for the maker to clean up
+      expect(await program.account.dealDetails.fetchNullable(takeDealPda)).to.be.null;
+      expect(await provider.connection.getAccountInfo(takeEscrowTokenAccA)).to.be.null;
+      expect(await provider.connection.getAccountInfo(takeEscrowTokenAccB)).to.be.null;
+    });
+  });
+
+  describe("Cancel", () => {
+    const cancelDealId = new anchor.BN(5);
+    let cancelDealPda: anchor.web3.PublicKey;
+    let cancelEscrowTokenAccA: anchor.web3.PublicKey;
+
+    before(async () => {
+      cancelDealPda = dealPda(cancelDealId);
+      [cancelEscrowTokenAccA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("token_a"), cancelDealPda.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .create(cancelDealId, new anchor.BN(100), new anchor.BN(50), inAnHour(), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+    });
+
+    it("Rejects cancelling a deal the taker already paid into", async () => {
+      // the open offer deal was filled by the taker earlier on
+      await expectError(
+        program.methods
+          .cancel(new anchor.BN(3))
+          .accounts({
+            maker: maker.publicKey,
+            tokenProgramA: TOKEN_2022_PROGRAM_ID,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([maker])
+          .rpc(),
+        "DealAlreadyFulfilled"
+      );
+    });
+
+    it("Refunds the maker and closes the deal even after dust was sent into escrow B", async () => {
+      const cancelEscrowTokenAccB = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("token_b"), cancelDealPda.toBuffer()],
+        program.programId
+      )[0];
+      const makerMintBata = ata(mintB.publicKey, maker.publicKey);
+      // anyone can send a unit of mint_b in, an escrow holding tokens can't be closed
+      await mintTo(provider.connection, tokenMaker.payer, mintB.publicKey, cancelEscrowTokenAccB, tokenMaker.publicKey, 1, [], undefined, TOKEN_2022_PROGRAM_ID);
+      const makerBefore = await provider.connection.getTokenAccountBalance(ataMakerMintA!);
+      const makerMintBBefore = await provider.connection.getTokenAccountBalance(makerMintBata);
+
+      await program.methods
+        .cancel(cancelDealId)
+        .accounts({
+          maker: maker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          makerTokenAccB: makerMintBata,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+
+      const makerAfter = await provider.connection.getTokenAccountBalance(ataMakerMintA!);
+      expect(Number(makerAfter.value.amount) - Number(makerBefore.value.amount)).eq(100);
+      const makerMintBAfter = await provider.connection.getTokenAccountBalance(makerMintBata);
+      expect(Number(makerMintBAfter.value.amount) - Number(makerMintBBefore.value.amount)).eq(1);
+
+      expect(await program.account.dealDetails.fetchNullable(cancelDealPda)).to.be.null;
+      expect(await provider.connection.getAccountInfo(cancelEscrowTokenAccA)).to.be.null;
+      expect(await provider.connection.getAccountInfo(cancelEscrowTokenAccB)).to.be.null;
+    });
+  });
+
+  describe("Expire", () => {
+    const expireDealId = new anchor.BN(6);
+    let expireDealPda: anchor.web3.PublicKey;
+    let expireEscrowTokenAccA: anchor.web3.PublicKey;
+
+    before(async () => {
+      expireDealPda = dealPda(expireDealId);
+      [expireEscrowTokenAccA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("token_a"), expireDealPda.toBuffer()],
+        program.programId
+      );
+    });
+
+    it("Rejects a deal that is already expired on creation", async () => {
+      await expectError(
+        program.methods
+          .create(expireDealId, new anchor.BN(100), new anchor.BN(50), new anchor.BN(Math.floor(Date.now() / 1000) - 10), false)
+          .accounts({
+            maker: maker.publicKey,
+            taker: taker.publicKey,
+            mintA: mintA.publicKey,
+            mintB: mintB.publicKey,
+            tokenProgramA: TOKEN_2022_PROGRAM_ID,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([maker])
+          .rpc(),
+        "InvalidExpiry"
+      );
+    });
+
+    it("Rejects expiring a deal that is still live", async () => {
+      await program.methods
+        .create(expireDealId, new anchor.BN(100), new anchor.BN(50), new anchor.BN(Math.floor(Date.now() / 1000) + 3), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+
+      await expectError(
+        program.methods
+          .expire(expireDealId)
+          .accounts({
+            signer: secondMaker.publicKey,
+            maker: maker.publicKey,
+            tokenProgramA: TOKEN_2022_PROGRAM_ID,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([secondMaker])
+          .rpc(),
+        "DealNotExpired"
+      );
+    });
+
+    it("Rejects deposits after expiry", async () => {
+      await new Promise((resolve) => setTimeout(resolve, 5000));
+
+      await expectError(
+        program.methods
+          .deposit(expireDealId, new anchor.BN(50), new anchor.BN(100), new anchor.BN(50), [])
+          .accounts({
+            maker: maker.publicKey,
+            taker: taker.publicKey,
+            mint: mintB.publicKey,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([taker])
+          .rpc(),
+        "DealExpired"
+      );
+    });
+
+    it("Anyone can refund the maker once the deal expired", async () => {
+      const makerBefore = await provider.connection.getTokenAccountBalance(ataMakerMintA!);
+
+      // cranked by someone who has nothing to do with the deal
+      await program.methods
+        .expire(expireDealId)
+        .accounts({
+          signer: secondMaker.publicKey,
+          maker: maker.publicKey,
+          mintA: mintA.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([secondMaker])
+        .rpc();
+
+      const makerAfter = await provider.connection.getTokenAccountBalance(ataMakerMintA!);
+      expect(Number(makerAfter.value.amount) - Number(makerBefore.value.amount)).eq(100);
+
+      expect(await program.account.dealDetails.fetchNullable(expireDealPda)).to.be.null;
+      expect(await provider.connection.getAccountInfo(expireEscrowTokenAccA)).to.be.null;
+    });
+  });
+
+  describe("Deal state machine", () => {
+    const stateDealId = new anchor.BN(7);
+    let stateDealPda: anchor.web3.PublicKey;
+    let stateEscrowTokenAccB: anchor.web3.PublicKey;
+    let stateTakerFillPda: anchor.web3.PublicKey;
+    let makerMintBata: anchor.web3.PublicKey;
+    let takerMintAata: anchor.web3.PublicKey;
+
+    const makerWithdraw = (dealId = stateDealId) =>
+      program.methods
+        .withdraw(dealId)
+        .accounts({
+          maker: maker.publicKey,
+          signer: maker.publicKey,
+          userTokenAcc: makerMintBata,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          mintExchange: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          fillDetails: null,
+        })
+        .signers([maker])
+        .rpc();
+
+    const takerWithdraw = (fillDetails: anchor.web3.PublicKey | null = stateTakerFillPda) =>
+      program.methods
+        .withdraw(stateDealId)
+        .accounts({
+          maker: maker.publicKey,
+          signer: taker.publicKey,
+          userTokenAcc: takerMintAata,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          mintExchange: mintA.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          fillDetails,
+        })
+        .signers([taker])
+        .rpc();
+
+    const takerDeposit = (amount: number, dealId = stateDealId) =>
+      program.methods
+        .deposit(dealId, new anchor.BN(amount), new anchor.BN(100), new anchor.BN(50), [])
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mint: mintB.publicKey,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([taker])
+        .rpc();
+
+    const cancel = (dealId: anchor.BN) =>
+      program.methods
+        .cancel(dealId)
+        .accounts({ maker: maker.publicKey, mintA: mintA.publicKey, tokenProgramA: TOKEN_2022_PROGRAM_ID, tokenProgramB: TOKEN_2022_PROGRAM_ID })
+        .signers([maker])
+        .rpc();
+
+    const fetchState = async () => (await program.account.dealDetails.fetch(stateDealPda)).state;
+
+    before(async () => {
+      stateDealPda = dealPda(stateDealId);
+      [stateEscrowTokenAccB] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("token_b"), stateDealPda.toBuffer()],
+        program.programId
+      );
+      [stateTakerFillPda] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("fill"), stateDealPda.toBuffer(), taker.publicKey.toBuffer()],
+        program.programId
+      );
+      makerMintBata = ata(mintB.publicKey, maker.publicKey);
+      takerMintAata = ata(mintA.publicKey, taker.publicKey);
+
+      // taker spent most of their mint_b in the earlier deals
+      await mintTo(
+        provider.connection,
+        tokenMaker.payer,
+        mintB.publicKey,
+        ataTakerMintB!,
+        tokenMaker.publicKey,
+        100,
+        [],
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      await program.methods
+        .create(stateDealId, new anchor.BN(100), new anchor.BN(50), inAnHour(), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+    });
+
+    it("Rejects maker withdraw before anything was filled", async () => {
+      await expectError(makerWithdraw(), "InvalidDealState");
+    });
+
+    it("Rejects close before the deal is completed", async () => {
+      await expectError(
+        program.methods
+          .close(stateDealId)
+          .accounts({ maker: maker.publicKey, tokenProgramA: TOKEN_2022_PROGRAM_ID, tokenProgramB: TOKEN_2022_PROGRAM_ID })
+          .signers([maker])
+          .rpc(),
+        "InvalidDealState"
+      );
+    });
+
+    it("Rejects cancel once the deal is partially filled", async () => {
+      await takerDeposit(20);
+      expect(await fetchState()).to.deep.equal({ partiallyFilled: {} });
+
+      await expectError(cancel(stateDealId), "DealPartiallyFilled");
+    });
+
+    it("Rejects take once the deal is partially filled", async () => {
+      await expectError(
+        program.methods
+          .take(stateDealId, new anchor.BN(100), new anchor.BN(50), [])
+          .accounts({ taker: taker.publicKey, maker: maker.publicKey, tokenProgramA: TOKEN_2022_PROGRAM_ID, tokenProgramB: TOKEN_2022_PROGRAM_ID })
+          .signers([taker])
+          .rpc(),
+        "DealPartiallyFilled"
+      );
+    });
+
+    it("Rejects another deposit once the deal is filled", async () => {
+      await takerDeposit(30);
+      expect(await fetchState()).to.deep.equal({ takerDeposited: {} });
+
+      await expectError(takerDeposit(1), "DealAlreadyFulfilled");
+    });
+
+    it("Rejects cancel once the deal is filled", async () => {
+      await expectError(cancel(stateDealId), "DealAlreadyFulfilled");
+    });
+
+    it("Rejects a second maker withdraw after the escrow is refilled", async () => {
+      await makerWithdraw();
+      expect(await fetchState()).to.deep.equal({ makerWithdrew: {} });
+
+      // anyone can send tokens into the escrow account, that must not reopen the maker's claim
+      await mintTo(
+        provider.connection,
+        tokenMaker.payer,
+        mintB.publicKey,
+        stateEscrowTokenAccB,
+        tokenMaker.publicKey,
+        10,
+        [],
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      await expectError(makerWithdraw(), "AlreadyClaimed");
+    });
+
+    it("Rejects a second taker withdraw once the deal is completed", async () => {
+      await takerWithdraw();
+      const deal = await program.account.dealDetails.fetch(stateDealPda);
+      expect(deal.state).to.deep.equal({ completed: {} });
+      expect(deal.makerClaimed).to.be.true;
+      expect(deal.takerClaimed).to.be.true;
+      // the refilled escrow account keeps the deal open, no maker account was passed to sweep it into
+
+      // the fill record went away with the first withdraw, without it the claim itself is what stops the taker
+      await expectError(takerWithdraw(null), "AlreadyClaimed");
+    });
+
+    it("Rejects maker withdraw once the deal is completed", async () => {
+      await expectError(makerWithdraw(), "AlreadyClaimed");
+    });
+
+    it("Rejects deposit once the deal is completed", async () => {
+      await expectError(takerDeposit(1), "DealAlreadyFulfilled");
+    });
+
+    it("Rejects cancel once the deal is completed", async () => {
+      await expectError(cancel(stateDealId), "DealAlreadyFulfilled");
+    });
+
+    it("Close sweeps the tokens sent into the escrow to the maker", async () => {
+      const makerBefore = await provider.connection.getTokenAccountBalance(makerMintBata);
+      await program.methods
+        .close(stateDealId)
+        .accounts({
+          maker: maker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          makerTokenAccB: makerMintBata,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+      const makerAfter = await provider.connection.getTokenAccountBalance(makerMintBata);
+
+      expect(Number(makerAfter.value.amount) - Number(makerBefore.value.amount)).eq(10);
+      expect(await program.account.dealDetails.fetchNullable(stateDealPda)).to.be.null;
+      expect(await provider.connection.getAccountInfo(stateEscrowTokenAccB)).to.be.null;
+    });
+
+    describe("Ended deals", () => {
+      // filled before its expiry, partially filled before its expiry and cancelled
+      const [filledDealId, partialDealId, cancelledDealId] = [new anchor.BN(31), new anchor.BN(32), new anchor.BN(33)];
+
+      const createDeal = (dealId: anchor.BN, expiresAt: anchor.BN) =>
+        program.methods
+          .create(dealId, new anchor.BN(100), new anchor.BN(50), expiresAt, false)
+          .accounts({
+            maker: maker.publicKey,
+            taker: taker.publicKey,
+            mintA: mintA.publicKey,
+            mintB: mintB.publicKey,
+            tokenProgramA: TOKEN_2022_PROGRAM_ID,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([maker])
+          .rpc();
+
+      const expire = (dealId: anchor.BN) =>
+        program.methods
+          .expire(dealId)
+          .accounts({
+            signer: secondMaker.publicKey,
+            maker: maker.publicKey,
+            mintA: mintA.publicKey,
+            tokenProgramA: TOKEN_2022_PROGRAM_ID,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([secondMaker])
+          .rpc();
+
+      before(async () => {
+        await fundParties(300, 70);
+        // just enough time on the cluster clock to create and fill both deals before they expire
+        const soon = new anchor.BN((await clusterTime()) + 3);
+        await createDeal(filledDealId, soon);
+        await createDeal(partialDealId, soon);
+        await createDeal(cancelledDealId, inAnHour());
+
+        await takerDeposit(50, filledDealId);
+        await takerDeposit(20, partialDealId);
+        await cancel(cancelledDealId);
+
+        // every deal below is past its expiry for real
+        await waitUntilPast(soon.toNumber());
+      });
+
+      it("Rejects expiring a filled deal once it is past its expiry", async () => {
+        await expectError(expire(filledDealId), "DealAlreadyFulfilled");
+      });
+
+      it("Rejects deposit once the deal is expired", async () => {
+        await expire(partialDealId);
+        const deal = await program.account.dealDetails.fetch(dealPda(partialDealId));
+        expect(deal.state).to.deep.equal({ expired: {} });
+
+        await expectError(takerDeposit(10, partialDealId), "InvalidDealState");
+      });
+
+      it("Rejects cancel once the deal is expired", async () => {
+        await expectError(cancel(partialDealId), "InvalidDealState");
+      });
+
+      it("Rejects a second maker withdraw once the deal is expired", async () => {
+        await makerWithdraw(partialDealId);
+        await expectError(makerWithdraw(partialDealId), "AlreadyClaimed");
+      });
+
+      // cancel closes every deal account, nothing can act on the deal afterwards
+      it("Rejects withdraw once the deal is cancelled", async () => {
+        await expectError(makerWithdraw(cancelledDealId), "AccountNotInitialized");
+      });
+
+      it("Rejects cancel once the deal is cancelled", async () => {
+        await expectError(cancel(cancelledDealId), "AccountNotInitialized");
+      });
+
+      it("Rejects deposit once the deal is cancelled", async () => {
+        await expectError(takerDeposit(10, cancelledDealId), "AccountNotInitialized");
+      });
+    });
+  });
+
+  describe("Mint and account validation", () => {
+    const validationDealId = new anchor.BN(8);
+    const mintC = anchor.web3.Keypair.generate();
+    let validationDealPda: anchor.web3.PublicKey;
+    let takerFillPda: anchor.web3.PublicKey;
+
+    const deposit = (mint: anchor.web3.PublicKey) =>
+      program.methods
+        .deposit(validationDealId, new anchor.BN(50), new anchor.BN(100), new anchor.BN(50), [])
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mint,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([taker])
+        .rpc();
+
+    before(async () => {
+      // unrelated mint used to impersonate the deal mints
+      await createMint(
+        provider.connection,
+        tokenMaker.payer,
+        tokenMaker.publicKey,
+        tokenMaker.publicKey,
+        2,
+        mintC,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      validationDealPda = dealPda(validationDealId);
+      [takerFillPda] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("fill"), validationDealPda.toBuffer(), taker.publicKey.toBuffer()],
+        program.programId
+      );
+    });
+
+    it("Rejects a deal with the same mint on both sides", async () => {
+      await expectError(
+        program.methods
+          .create(validationDealId, new anchor.BN(100), new anchor.BN(50), inAnHour(), false)
+          .accounts({
+            maker: maker.publicKey,
+            taker: taker.publicKey,
+            mintA: mintA.publicKey,
+            mintB: mintA.publicKey,
+            tokenProgramA: TOKEN_2022_PROGRAM_ID,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([maker])
+          .rpc(),
+        "IdenticalMints"
+      );
+    });
+
+    it("Rejects a deal with a zero amount", async () => {
+      await expectError(
+        program.methods
+          .create(validationDealId, new anchor.BN(100), new anchor.BN(0), inAnHour(), false)
+          .accounts({
+            maker: maker.publicKey,
+            taker: taker.publicKey,
+            mintA: mintA.publicKey,
+            mintB: mintB.publicKey,
+            tokenProgramA: TOKEN_2022_PROGRAM_ID,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([maker])
+          .rpc(),
+        "InvalidAmount"
+      );
+    });
+
+    it("Rejects deposits with a mint the deal was not created with", async () => {
+      await program.methods
+        .create(validationDealId, new anchor.BN(100), new anchor.BN(50), inAnHour(), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+
+      await expectError(deposit(mintC.publicKey), "InvalidMint");
+      // the maker's mint is not a valid substitute either
+      await expectError(deposit(mintA.publicKey), "InvalidMint");
+
+      await deposit(mintB.publicKey);
+    });
+
+    it("Rejects a maker withdraw paid out in the wrong mint", async () => {
+      const makerMintAata = ata(mintA.publicKey, maker.publicKey);
+
+      await expectError(
+        program.methods
+          .withdraw(validationDealId)
+          .accounts({
+            maker: maker.publicKey,
+            signer: maker.publicKey,
+            userTokenAcc: makerMintAata,
+            mintA: mintA.publicKey,
+            mintB: mintB.publicKey,
+            mintExchange: mintA.publicKey,
+            tokenProgramA: TOKEN_2022_PROGRAM_ID,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+            fillDetails: null,
+          })
+          .signers([maker])
+          .rpc(),
+        "InvalidMint"
+      );
+    });
+
+    it("Rejects withdraw with deal mints swapped for another mint", async () => {
+      const makerMintBata = ata(mintB.publicKey, maker.publicKey);
+
+      await expectError(
+        program.methods
+          .withdraw(validationDealId)
+          .accounts({
+            maker: maker.publicKey,
+            signer: maker.publicKey,
+            userTokenAcc: makerMintBata,
+            mintA: mintC.publicKey,
+            mintB: mintB.publicKey,
+            mintExchange: mintB.publicKey,
+            tokenProgramA: TOKEN_2022_PROGRAM_ID,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+            fillDetails: null,
+          })
+          .signers([maker])
+          .rpc(),
+        "InvalidMint"
+      );
+
+      await expectError(
+        program.methods
+          .withdraw(validationDealId)
+          .accounts({
+            maker: maker.publicKey,
+            signer: maker.publicKey,
+            userTokenAcc: makerMintBata,
+            mintA: mintA.publicKey,
+            mintB: mintA.publicKey,
+            mintExchange: mintB.publicKey,
+            tokenProgramA: TOKEN_2022_PROGRAM_ID,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+            fillDetails: null,
+          })
+          .signers([maker])
+          .rpc(),
+        "InvalidMint"
+      );
+    });
+
+    it("Rejects a taker withdraw into an account they do not own", async () => {
+      const makerMintAata = ata(mintA.publicKey, maker.publicKey);
+
+      await expectError(
+        program.methods
+          .withdraw(validationDealId)
+          .accounts({
+            maker: maker.publicKey,
+            signer: taker.publicKey,
+            userTokenAcc: makerMintAata,
+            mintA: mintA.publicKey,
+            mintB: mintB.publicKey,
+            mintExchange: mintA.publicKey,
+            tokenProgramA: TOKEN_2022_PROGRAM_ID,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+            fillDetails: takerFillPda,
+          })
+          .signers([taker])
+          .rpc(),
+        "ConstraintTokenOwner"
+      );
+    });
+  });
+
+  describe("Native SOL legs", () => {
+    const solForTokenDealId = new anchor.BN(9);
+    const tokenForSolDealId = new anchor.BN(10);
+    const cancelSolDealId = new anchor.BN(11);
+    const lamports = (sol: number) => new anchor.BN(sol * anchor.web3.LAMPORTS_PER_SOL);
+
+    before(async () => {
+      await mintTo(
+        provider.connection,
+        tokenMaker.payer,
+        mintB.publicKey,
+        ataTakerMintB!,
+        tokenMaker.publicKey,
+        100,
+        [],
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+    });
+
+    it("Rejects a deal with native SOL on both sides", async () => {
+      await expectError(
+        program.methods
+          .create(solForTokenDealId, lamports(1), lamports(1), inAnHour(), false)
+          .accountsPartial({
+            maker: maker.publicKey,
+            taker: taker.publicKey,
+            mintA: null,
+            mintB: null,
+            userTokenAccA: null,
+            escrowTokenAccA: null,
+            escrowTokenAccB: null,
+            tokenProgramA: TOKEN_2022_PROGRAM_ID,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([maker])
+          .rpc(),
+        "IdenticalMints"
+      );
+    });
+
+    it("Taker pays for mint_a in SOL and both sides withdraw", async () => {
+      const dealDetails = dealPda(solForTokenDealId);
+      await program.methods
+        .create(solForTokenDealId, new anchor.BN(100), lamports(0.5), inAnHour(), false)
+        .accountsPartial({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: null,
+          escrowTokenAccB: null,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+
+      const userBDetails = await program.account.userEscrowDetails.fetch(
+        anchor.web3.PublicKey.findProgramAddressSync(
+          [Buffer.from("user_b_details"), dealDetails.toBuffer()],
+          program.programId
+        )[0]
+      );
+      expect(userBDetails.mint.equals(anchor.web3.PublicKey.default)).to.be.true;
+
+      await program.methods
+        .deposit(solForTokenDealId, lamports(0.5), new anchor.BN(100), lamports(0.5), [])
+        .accountsPartial({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mint: null,
+          userTokenAccB: null,
+          escrowTokenAccA: null,
+          escrowTokenAccB: null,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([taker])
+        .rpc();
+      expect((await program.account.dealDetails.fetch(dealDetails)).state).to.deep.equal({ takerDeposited: {} });
+
+      const makerLamportsBefore = await provider.connection.getBalance(maker.publicKey);
+      await program.methods
+        .withdraw(solForTokenDealId)
+        .accountsPartial({
+          maker: maker.publicKey,
+          signer: maker.publicKey,
+          userTokenAcc: null,
+          mintA: mintA.publicKey,
+          mintB: null,
+          mintExchange: null,
+          escrowTokenAccB: null,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          fillDetails: null,
+        })
+        .signers([maker])
+        .rpc();
+      const makerLamportsAfter = await provider.connection.getBalance(maker.publicKey);
+      // maker pays the transaction fee out of the proceeds
+      expect(makerLamportsAfter - makerLamportsBefore).greaterThan(lamports(0.49).toNumber());
+
+      const takerMintAata = ata(mintA.publicKey, taker.publicKey);
+      const takerMintABefore = await provider.connection.getTokenAccountBalance(takerMintAata);
+      await program.methods
+        .withdraw(solForTokenDealId)
+        .accountsPartial({
+          maker: maker.publicKey,
+          signer: taker.publicKey,
+          userTokenAcc: takerMintAata,
+          mintA: mintA.publicKey,
+          mintB: null,
+          mintExchange: mintA.publicKey,
+          escrowTokenAccB: null,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          fillDetails: anchor.web3.PublicKey.findProgramAddressSync(
+            [Buffer.from("fill"), dealDetails.toBuffer(), taker.publicKey.toBuffer()],
+            program.programId
+          )[0],
+        })
+        .signers([taker])
+        .rpc();
+      const takerMintAAfter = await provider.connection.getTokenAccountBalance(takerMintAata);
+      expect(Number(takerMintAAfter.value.amount) - Number(takerMintABefore.value.amount)).eq(100);
+
+      // the last withdraw closed the deal and swept the controller back to the maker
+      const [controller] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("controller"), maker.publicKey.toBuffer(), dealIdSeed(solForTokenDealId)],
+        program.programId
+      );
+      expect(await program.account.dealDetails.fetchNullable(dealDetails)).to.be.null;
+      expect(await provider.connection.getBalance(controller)).eq(0);
+    });
+
+    it("Maker sells SOL for mint_b through take", async () => {
+      await program.methods
+        .create(tokenForSolDealId, lamports(1), new anchor.BN(50), inAnHour(), false)
+        .accountsPartial({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: null,
+          mintB: mintB.publicKey,
+          userTokenAccA: null,
+          escrowTokenAccA: null,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+
+      const makerMintBata = ata(mintB.publicKey, maker.publicKey);
+      const makerMintBBefore = await provider.connection.getTokenAccountBalance(makerMintBata);
+      const takerLamportsBefore = await provider.connection.getBalance(taker.publicKey);
+
+      await program.methods
+        .take(tokenForSolDealId, lamports(1), new anchor.BN(50), [])
+        .accountsPartial({
+          taker: taker.publicKey,
+          maker: maker.publicKey,
+          mintA: null,
+          mintB: mintB.publicKey,
+          escrowTokenAccA: null,
+          takerTokenAccA: null,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([taker])
+        .rpc();
+
+      const makerMintBAfter = await provider.connection.getTokenAccountBalance(makerMintBata);
+      const takerLamportsAfter = await provider.connection.getBalance(taker.publicKey);
+      expect(Number(makerMintBAfter.value.amount) - Number(makerMintBBefore.value.amount)).eq(50);
+      expect(takerLamportsAfter - takerLamportsBefore).greaterThan(lamports(0.99).toNumber());
+      expect(await program.account.dealDetails.fetchNullable(dealPda(tokenForSolDealId))).to.be.null;
+    });
+
+    it("Cancel refunds native SOL to the maker", async () => {
+      await program.methods
+        .create(cancelSolDealId, lamports(0.2), new anchor.BN(50), inAnHour(), false)
+        .accountsPartial({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: null,
+          mintB: mintB.publicKey,
+          userTokenAccA: null,
+          escrowTokenAccA: null,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+
+      const makerLamportsBefore = await provider.connection.getBalance(maker.publicKey);
+      await program.methods
+        .cancel(cancelSolDealId)
+        .accountsPartial({
+          maker: maker.publicKey,
+          mintA: null,
+          escrowTokenAccA: null,
+          makerTokenAccA: null,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+      const makerLamportsAfter = await provider.connection.getBalance(maker.publicKey);
 
-    const tx = await program.methods.close().accounts({
+      // refund plus the rent of every closed account outweighs the fee
+      expect(makerLamportsAfter - makerLamportsBefore).greaterThan(lamports(0.2).toNumber());
+      expect(await program.account.dealDetails.fetchNullable(dealPda(cancelSolDealId))).to.be.null;
+    });
+  });
+
+  describe("Wrapped SOL", () => {
+    const wsolForTokenDealId = new anchor.BN(12);
+    const tokenForWsolDealId = new anchor.BN(13);
+    const lamports = (sol: number) => new anchor.BN(sol * anchor.web3.LAMPORTS_PER_SOL);
+
+    const fillPda = (id: anchor.BN) =>
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("fill"), dealPda(id).toBuffer(), taker.publicKey.toBuffer()],
+        program.programId
+      )[0];
+
+    before(async () => {
+      // token-2022 does not ship its native mint on a fresh validator
+      if (!(await provider.connection.getAccountInfo(NATIVE_MINT_2022))) {
+        await createNativeMint(provider.connection, tokenMaker.payer, undefined, NATIVE_MINT_2022, TOKEN_2022_PROGRAM_ID);
+      }
+    });
+
+    it("Taker pays in wSOL straight from their lamports and the maker is paid out unwrapped", async () => {
+      await program.methods
+        .create(wsolForTokenDealId, new anchor.BN(100), lamports(0.3), inAnHour(), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: NATIVE_MINT_2022,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+
+      const takerLamportsBefore = await provider.connection.getBalance(taker.publicKey);
+      await program.methods
+        .deposit(wsolForTokenDealId, lamports(0.3), new anchor.BN(100), lamports(0.3), [])
+        .accountsPartial({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mint: NATIVE_MINT_2022,
+          userTokenAccB: null,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([taker])
+        .rpc();
+      const takerLamportsAfter = await provider.connection.getBalance(taker.publicKey);
+      expect(takerLamportsBefore - takerLamportsAfter).greaterThanOrEqual(lamports(0.3).toNumber());
+
+      const [escrowWsol] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("token_b"), dealPda(wsolForTokenDealId).toBuffer()],
+        program.programId
+      );
+      expect((await provider.connection.getTokenAccountBalance(escrowWsol)).value.amount).eq(lamports(0.3).toString());
+
+      const makerLamportsBefore = await provider.connection.getBalance(maker.publicKey);
+      await program.methods
+        .withdraw(wsolForTokenDealId)
+        .accountsPartial({
+          maker: maker.publicKey,
+          signer: maker.publicKey,
+          userTokenAcc: null,
+          mintA: mintA.publicKey,
+          mintB: NATIVE_MINT_2022,
+          mintExchange: NATIVE_MINT_2022,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          fillDetails: null,
+        })
+        .signers([maker])
+        .rpc();
+      const makerLamportsAfter = await provider.connection.getBalance(maker.publicKey);
+      expect(makerLamportsAfter - makerLamportsBefore).greaterThan(lamports(0.29).toNumber());
+
+      // the throwaway unwrap account does not outlive the instruction
+      const [unwrapTokenAcc] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("unwrap"), dealPda(wsolForTokenDealId).toBuffer(), maker.publicKey.toBuffer()],
+        program.programId
+      );
+      expect(await provider.connection.getAccountInfo(unwrapTokenAcc)).to.be.null;
+    });
+
+    it("Maker offers wSOL wrapped from their lamports and the taker is paid out unwrapped", async () => {
+      await program.methods
+        .create(tokenForWsolDealId, lamports(0.2), new anchor.BN(10), inAnHour(), false)
+        .accountsPartial({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: NATIVE_MINT_2022,
+          mintB: mintB.publicKey,
+          userTokenAccA: null,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+
+      await program.methods
+        .deposit(tokenForWsolDealId, new anchor.BN(10), lamports(0.2), new anchor.BN(10), [])
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mint: mintB.publicKey,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([taker])
+        .rpc();
+
+      const takerLamportsBefore = await provider.connection.getBalance(taker.publicKey);
+      await program.methods
+        .withdraw(tokenForWsolDealId)
+        .accountsPartial({
+          maker: maker.publicKey,
+          signer: taker.publicKey,
+          userTokenAcc: null,
+          mintA: NATIVE_MINT_2022,
+          mintB: mintB.publicKey,
+          mintExchange: NATIVE_MINT_2022,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          fillDetails: fillPda(tokenForWsolDealId),
+        })
+        .signers([taker])
+        .rpc();
+      const takerLamportsAfter = await provider.connection.getBalance(taker.publicKey);
+      expect(takerLamportsAfter - takerLamportsBefore).greaterThan(lamports(0.19).toNumber());
+    });
+
+    it("Lamports sent to the unwrap address up front don't block the payout", async () => {
+      const prefundedDealId = new anchor.BN(29);
+      await fundParties(100, 0);
+      await program.methods
+        .create(prefundedDealId, new anchor.BN(100), lamports(0.1), inAnHour(), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: NATIVE_MINT_2022,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+      await program.methods
+        .deposit(prefundedDealId, lamports(0.1), new anchor.BN(100), lamports(0.1), [])
+        .accountsPartial({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mint: NATIVE_MINT_2022,
+          userTokenAccB: null,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([taker])
+        .rpc();
+
+      const [unwrapTokenAcc] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("unwrap"), dealPda(prefundedDealId).toBuffer(), maker.publicKey.toBuffer()],
+        program.programId
+      );
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          anchor.web3.SystemProgram.transfer({ fromPubkey: secondMaker.publicKey, toPubkey: unwrapTokenAcc, lamports: 1_000_000 })
+        ),
+        [secondMaker]
+      );
+
+      const makerLamportsBefore = await provider.connection.getBalance(maker.publicKey);
+      await program.methods
+        .withdraw(prefundedDealId)
+        .accountsPartial({
+          maker: maker.publicKey,
+          signer: maker.publicKey,
+          userTokenAcc: null,
+          mintA: mintA.publicKey,
+          mintB: NATIVE_MINT_2022,
+          mintExchange: NATIVE_MINT_2022,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          fillDetails: null,
+        })
+        .signers([maker])
+        .rpc();
+      const makerLamportsAfter = await provider.connection.getBalance(maker.publicKey);
+      expect(makerLamportsAfter - makerLamportsBefore).greaterThan(lamports(0.099).toNumber());
+      expect(await provider.connection.getAccountInfo(unwrapTokenAcc)).to.be.null;
+    });
+
+    it("Expire refunds a maker who wrapped from lamports in lamports", async () => {
+      const expiringDealId = new anchor.BN(30);
+      await program.methods
+        .create(expiringDealId, lamports(0.2), new anchor.BN(10), new anchor.BN(Math.floor(Date.now() / 1000) + 3), false)
+        .accountsPartial({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: NATIVE_MINT_2022,
+          mintB: mintB.publicKey,
+          userTokenAccA: null,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+      await new Promise((resolve) => setTimeout(resolve, 5000));
+
+      // cranked by someone else, the maker has no wSOL account to be refunded to
+      const makerLamportsBefore = await provider.connection.getBalance(maker.publicKey);
+      await program.methods
+        .expire(expiringDealId)
+        .accountsPartial({
+          signer: secondMaker.publicKey,
+          maker: maker.publicKey,
+          mintA: NATIVE_MINT_2022,
+          makerTokenAccA: null,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([secondMaker])
+        .rpc();
+      const makerLamportsAfter = await provider.connection.getBalance(maker.publicKey);
+      expect(makerLamportsAfter - makerLamportsBefore).greaterThan(lamports(0.2).toNumber());
+      expect(await program.account.dealDetails.fetchNullable(dealPda(expiringDealId))).to.be.null;
+    });
+  });
+
+  describe("Mixed token programs", () => {
+    const mixedDealId = new anchor.BN(14);
+    const legacyMint = anchor.web3.Keypair.generate();
+    let makerLegacyAta: anchor.web3.PublicKey;
+
+    before(async () => {
+      await createMint(provider.connection, tokenMaker.payer, tokenMaker.publicKey, null, 0, legacyMint, undefined, TOKEN_PROGRAM_ID);
+      makerLegacyAta = await createAta(legacyMint.publicKey, maker.publicKey, 100, TOKEN_PROGRAM_ID);
+    });
+
+    it("Rejects a token program that does not own the mint", async () => {
+      await expectError(
+        program.methods
+          .create(mixedDealId, new anchor.BN(100), new anchor.BN(10), inAnHour(), false)
+          .accounts({
+            maker: maker.publicKey,
+            taker: taker.publicKey,
+            mintA: legacyMint.publicKey,
+            mintB: mintB.publicKey,
+            tokenProgramA: TOKEN_2022_PROGRAM_ID,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([maker])
+          .rpc(),
+        "ConstraintMintTokenProgram"
+      );
+    });
+
+    it("Trades an SPL Token mint for a Token-2022 mint", async () => {
+      await program.methods
+        .create(mixedDealId, new anchor.BN(100), new anchor.BN(10), inAnHour(), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: legacyMint.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+
+      await program.methods
+        .deposit(mixedDealId, new anchor.BN(10), new anchor.BN(100), new anchor.BN(10), [])
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mint: mintB.publicKey,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([taker])
+        .rpc();
+
+      const makerMintBata = ata(mintB.publicKey, maker.publicKey);
+      const makerMintBBefore = await provider.connection.getTokenAccountBalance(makerMintBata);
+      await program.methods
+        .withdraw(mixedDealId)
+        .accounts({
+          maker: maker.publicKey,
+          signer: maker.publicKey,
+          userTokenAcc: makerMintBata,
+          mintA: legacyMint.publicKey,
+          mintB: mintB.publicKey,
+          mintExchange: mintB.publicKey,
+          tokenProgramA: TOKEN_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          fillDetails: null,
+        })
+        .signers([maker])
+        .rpc();
+      const makerMintBAfter = await provider.connection.getTokenAccountBalance(makerMintBata);
+      expect(Number(makerMintBAfter.value.amount) - Number(makerMintBBefore.value.amount)).eq(10);
+
+      const takerLegacyAta = await createAta(legacyMint.publicKey, taker.publicKey, 0, TOKEN_PROGRAM_ID);
+      await program.methods
+        .withdraw(mixedDealId)
+        .accounts({
+          maker: maker.publicKey,
+          signer: taker.publicKey,
+          userTokenAcc: takerLegacyAta,
+          mintA: legacyMint.publicKey,
+          mintB: mintB.publicKey,
+          mintExchange: legacyMint.publicKey,
+          tokenProgramA: TOKEN_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          fillDetails: anchor.web3.PublicKey.findProgramAddressSync(
+            [Buffer.from("fill"), dealPda(mixedDealId).toBuffer(), taker.publicKey.toBuffer()],
+            program.programId
+          )[0],
+        })
+        .signers([taker])
+        .rpc();
+
+      expect((await provider.connection.getTokenAccountBalance(takerLegacyAta)).value.amount).eq("100");
+      expect(await program.account.dealDetails.fetchNullable(dealPda(mixedDealId))).to.be.null;
+    });
+  });
+
+  describe("Transfer fee mints", () => {
+    const feeDealId = new anchor.BN(15);
+    const feeMint = anchor.web3.Keypair.generate();
+    // 1% fee, capped far above anything the test moves
+    const feeBasisPoints = 100;
+    let takerFeeAta: anchor.web3.PublicKey;
+
+    before(async () => {
+      const mintLen = getMintLen([ExtensionType.TransferFeeConfig]);
+      const tx = new anchor.web3.Transaction().add(
+        anchor.web3.SystemProgram.createAccount({
+          fromPubkey: tokenMaker.publicKey,
+          newAccountPubkey: feeMint.publicKey,
+          space: mintLen,
+          lamports: await provider.connection.getMinimumBalanceForRentExemption(mintLen),
+          programId: TOKEN_2022_PROGRAM_ID,
+        }),
+        createInitializeTransferFeeConfigInstruction(
+          feeMint.publicKey,
+          tokenMaker.publicKey,
+          tokenMaker.publicKey,
+          feeBasisPoints,
+          BigInt(1_000_000),
+          TOKEN_2022_PROGRAM_ID
+        ),
+        createInitializeMintInstruction(feeMint.publicKey, 0, tokenMaker.publicKey, null, TOKEN_2022_PROGRAM_ID)
+      );
+      await provider.sendAndConfirm(tx, [feeMint]);
+
+      takerFeeAta = await createAta(feeMint.publicKey, taker.publicKey, 2000);
+    });
+
+    it("Taker covers the fee so the deal is filled at its full amount", async () => {
+      await program.methods
+        .create(feeDealId, new anchor.BN(100), new anchor.BN(1000), inAnHour(), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: feeMint.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+
+      await program.methods
+        .deposit(feeDealId, new anchor.BN(1000), new anchor.BN(100), new anchor.BN(1000), [])
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mint: feeMint.publicKey,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([taker])
+        .rpc();
+
+      const [escrowFeeAcc] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("token_b"), dealPda(feeDealId).toBuffer()],
+        program.programId
+      );
+      expect((await provider.connection.getTokenAccountBalance(escrowFeeAcc)).value.amount).eq("1000");
+      // 1000 net needs 1011 gross at 1%, the fee rounds up
+      expect((await provider.connection.getTokenAccountBalance(takerFeeAta)).value.amount).eq("989");
+      expect((await program.account.dealDetails.fetch(dealPda(feeDealId))).state).to.deep.equal({ takerDeposited: {} });
+    });
+
+    it("Maker withdraw is charged the fee on the way out", async () => {
+      const makerFeeAta = await createAta(feeMint.publicKey, maker.publicKey);
+
+      await program.methods
+        .withdraw(feeDealId)
+        .accounts({
+          maker: maker.publicKey,
+          signer: maker.publicKey,
+          userTokenAcc: makerFeeAta,
+          mintA: mintA.publicKey,
+          mintB: feeMint.publicKey,
+          mintExchange: feeMint.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          fillDetails: null,
+        })
+        .signers([maker])
+        .rpc();
+
+      expect((await provider.connection.getTokenAccountBalance(makerFeeAta)).value.amount).eq("990");
+    });
+  });
+
+  describe("Transfer hook mints", () => {
+    const hookDealId = new anchor.BN(16);
+    const hookProgram = anchor.workspace.transferHookExample as Program<TransferHookExample>;
+    const hookedMint = anchor.web3.Keypair.generate();
+    let remainingAccounts: anchor.web3.AccountMeta[];
+    let counterPda: anchor.web3.PublicKey;
+
+    const createHookDeal = () =>
+      program.methods
+        .create(hookDealId, new anchor.BN(100), new anchor.BN(10), inAnHour(), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: hookedMint.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker]);
+
+    before(async () => {
+      const mintLen = getMintLen([ExtensionType.TransferHook]);
+      const tx = new anchor.web3.Transaction().add(
+        anchor.web3.SystemProgram.createAccount({
+          fromPubkey: tokenMaker.publicKey,
+          newAccountPubkey: hookedMint.publicKey,
+          space: mintLen,
+          lamports: await provider.connection.getMinimumBalanceForRentExemption(mintLen),
+          programId: TOKEN_2022_PROGRAM_ID,
+        }),
+        createInitializeTransferHookInstruction(
+          hookedMint.publicKey,
+          tokenMaker.publicKey,
+          hookProgram.programId,
+          TOKEN_2022_PROGRAM_ID
+        ),
+        createInitializeMintInstruction(hookedMint.publicKey, 0, tokenMaker.publicKey, null, TOKEN_2022_PROGRAM_ID)
+      );
+      await provider.sendAndConfirm(tx, [hookedMint]);
+
+      await hookProgram.methods
+        .initializeExtraAccountMetaList()
+        .accounts({ payer: tokenMaker.publicKey, mint: hookedMint.publicKey })
+        .rpc();
+
+      await createAta(hookedMint.publicKey, maker.publicKey, 100);
+
+      const [extraAccountMetaList] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("extra-account-metas"), hookedMint.publicKey.toBuffer()],
+        hookProgram.programId
+      );
+      [counterPda] = anchor.web3.PublicKey.findProgramAddressSync([Buffer.from("counter")], hookProgram.programId);
+
+      // everything the hook needs, in no particular order, the escrow resolves them from the extra-account-metas
+      remainingAccounts = [
+        { pubkey: extraAccountMetaList, isSigner: false, isWritable: false },
+        { pubkey: counterPda, isSigner: false, isWritable: true },
+        { pubkey: hookProgram.programId, isSigner: false, isWritable: false },
+      ];
+    });
+
+    it("Fails to escrow a hooked mint without the hook accounts", async () => {
+      await rejection(createHookDeal().rpc());
+      expect(await program.account.dealDetails.fetchNullable(dealPda(hookDealId))).to.be.null;
+    });
+
+    it("Escrows and releases a hooked mint with the hook accounts forwarded", async () => {
+      await createHookDeal().remainingAccounts(remainingAccounts).rpc();
+      expect((await hookProgram.account.transferCounter.fetch(counterPda)).transfers.toNumber()).eq(1);
+
+      await program.methods
+        .deposit(hookDealId, new anchor.BN(10), new anchor.BN(100), new anchor.BN(10), [])
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mint: mintB.publicKey,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([taker])
+        .rpc();
+
+      const takerHookedAta = await createAta(hookedMint.publicKey, taker.publicKey);
+
+      await program.methods
+        .withdraw(hookDealId)
+        .accounts({
+          maker: maker.publicKey,
+          signer: taker.publicKey,
+          userTokenAcc: takerHookedAta,
+          mintA: hookedMint.publicKey,
+          mintB: mintB.publicKey,
+          mintExchange: hookedMint.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          fillDetails: anchor.web3.PublicKey.findProgramAddressSync(
+            [Buffer.from("fill"), dealPda(hookDealId).toBuffer(), taker.publicKey.toBuffer()],
+            program.programId
+          )[0],
+        })
+        .remainingAccounts(remainingAccounts)
+        .signers([taker])
+        .rpc();
+
+      expect((await provider.connection.getTokenAccountBalance(takerHookedAta)).value.amount).eq("100");
+      expect((await hookProgram.account.transferCounter.fetch(counterPda)).transfers.toNumber()).eq(2);
+    });
+  });
+
+  describe("Unsafe mint extensions", () => {
+    const unsafeDealId = new anchor.BN(17);
+
+    const createMintWithExtension = async (
+      extension: ExtensionType,
+      initExtension: (mint: anchor.web3.PublicKey) => anchor.web3.TransactionInstruction
+    ) => {
+      const mint = anchor.web3.Keypair.generate();
+      const mintLen = getMintLen([extension]);
+      const tx = new anchor.web3.Transaction().add(
+        anchor.web3.SystemProgram.createAccount({
+          fromPubkey: tokenMaker.publicKey,
+          newAccountPubkey: mint.publicKey,
+          space: mintLen,
+          lamports: await provider.connection.getMinimumBalanceForRentExemption(mintLen),
+          programId: TOKEN_2022_PROGRAM_ID,
+        }),
+        initExtension(mint.publicKey),
+        createInitializeMintInstruction(mint.publicKey, 0, tokenMaker.publicKey, tokenMaker.publicKey, TOKEN_2022_PROGRAM_ID)
+      );
+      await provider.sendAndConfirm(tx, [mint]);
+      return mint.publicKey;
+    };
+
+    const expectCreateRejected = async (mintB: anchor.web3.PublicKey, code: string) => {
+      await expectError(
+        program.methods
+          .create(unsafeDealId, new anchor.BN(100), new anchor.BN(10), inAnHour(), false)
+          .accounts({
+            maker: maker.publicKey,
+            taker: taker.publicKey,
+            mintA: mintA.publicKey,
+            mintB,
+            tokenProgramA: TOKEN_2022_PROGRAM_ID,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([maker])
+          .rpc(),
+        code
+      );
+    };
+
+    it("Rejects a non-transferable mint", async () => {
+      const mint = await createMintWithExtension(ExtensionType.NonTransferable, (mint) =>
+        createInitializeNonTransferableMintInstruction(mint, TOKEN_2022_PROGRAM_ID)
+      );
+      await expectCreateRejected(mint, "NonTransferableMint");
+    });
+
+    it("Rejects a mint with a permanent delegate", async () => {
+      const mint = await createMintWithExtension(ExtensionType.PermanentDelegate, (mint) =>
+        createInitializePermanentDelegateInstruction(mint, tokenMaker.publicKey, TOKEN_2022_PROGRAM_ID)
+      );
+      await expectCreateRejected(mint, "PermanentDelegateMint");
+    });
+
+    it("Rejects a mint that freezes new accounts by default", async () => {
+      const mint = await createMintWithExtension(ExtensionType.DefaultAccountState, (mint) =>
+        createInitializeDefaultAccountStateInstruction(mint, AccountState.Frozen, TOKEN_2022_PROGRAM_ID)
+      );
+      await expectCreateRejected(mint, "FrozenByDefaultMint");
+    });
+  });
+
+  describe("Basket deals", () => {
+    const basketDealId = new anchor.BN(18);
+    // mintC rides along with mint_a on the maker side, mintD with mint_b on the taker side
+    const [mintC, mintD] = [anchor.web3.Keypair.generate(), anchor.web3.Keypair.generate()];
+
+    const basketPda = () =>
+      anchor.web3.PublicKey.findProgramAddressSync([Buffer.from("basket"), dealPda(basketDealId).toBuffer()], program.programId)[0];
+    const legEscrowPda = (index: number) =>
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("leg"), dealPda(basketDealId).toBuffer(), Buffer.from([index])],
+        program.programId
+      )[0];
+
+    // every leg in the order it was added: escrow, mint, the user's account for it and the token program
+    const legAccounts = (owner: anchor.web3.PublicKey): anchor.web3.AccountMeta[] =>
+      [mintC, mintD].flatMap((mint, index) => [
+        { pubkey: legEscrowPda(index), isSigner: false, isWritable: true },
+        { pubkey: mint.publicKey, isSigner: false, isWritable: false },
+        { pubkey: ata(mint.publicKey, owner), isSigner: false, isWritable: true },
+        { pubkey: TOKEN_2022_PROGRAM_ID, isSigner: false, isWritable: false },
+      ]);
+
+    const deposit = (amount: number, remainingAccounts: anchor.web3.AccountMeta[]) =>
+      program.methods
+        .deposit(basketDealId, new anchor.BN(amount), new anchor.BN(100), new anchor.BN(10), [])
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mint: mintB.publicKey,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          basketDetails: basketPda(),
+        })
+        .remainingAccounts(remainingAccounts)
+        .signers([taker])
+        .rpc();
+
+    before(async () => {
+      for (const mint of [mintC, mintD]) {
+        await createMint(provider.connection, tokenMaker.payer, tokenMaker.publicKey, null, 0, mint, undefined, TOKEN_2022_PROGRAM_ID);
+      }
+      await createAta(mintC.publicKey, maker.publicKey, 50);
+      await createAta(mintC.publicKey, taker.publicKey);
+      await createAta(mintD.publicKey, maker.publicKey);
+      await createAta(mintD.publicKey, taker.publicKey, 40);
+      await createAta(mintA.publicKey, taker.publicKey);
+      await fundParties(100, 10);
+
+      await program.methods
+        .create(basketDealId, new anchor.BN(100), new anchor.BN(10), inAnHour(), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+    });
+
+    it("Maker adds a leg to each side", async () => {
+      await program.methods
+        .addLeg(basketDealId, { maker: {} }, new anchor.BN(50))
+        .accountsPartial({
+          maker: maker.publicKey,
+          dealDetails: dealPda(basketDealId),
+          mint: mintC.publicKey,
+          legEscrowTokenAcc: legEscrowPda(0),
+          makerTokenAcc: ata(mintC.publicKey, maker.publicKey),
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+
+      await program.methods
+        .addLeg(basketDealId, { taker: {} }, new anchor.BN(40))
+        .accountsPartial({
+          maker: maker.publicKey,
+          dealDetails: dealPda(basketDealId),
+          mint: mintD.publicKey,
+          legEscrowTokenAcc: legEscrowPda(1),
+          makerTokenAcc: null,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+
+      const basket = await program.account.basketDetails.fetch(basketPda());
+      expect(basket.legs.map((leg) => leg.mintAmt.toNumber())).to.deep.equal([50, 40]);
+      expect((await provider.connection.getTokenAccountBalance(legEscrowPda(0))).value.amount).eq("50");
+      expect((await program.account.dealDetails.fetch(dealPda(basketDealId))).basketLegs).eq(2);
+    });
+
+    it("Rejects milestones on a basket deal", async () => {
+      await expectError(
+        program.methods
+          .setMilestones(basketDealId, [new anchor.BN(50), new anchor.BN(50)])
+          .accounts({ maker: maker.publicKey })
+          .signers([maker])
+          .rpc(),
+        "MilestonesWithBasket"
+      );
+    });
+
+    it("Rejects a deposit that does not fund every leg", async () => {
+      await expectError(deposit(5, legAccounts(taker.publicKey)), "BasketRequiresFullFill");
+      await expectError(deposit(10, []), "MissingBasketAccounts");
+    });
+
+    it("Settles every leg of the basket", async () => {
+      await deposit(10, legAccounts(taker.publicKey));
+      expect((await provider.connection.getTokenAccountBalance(legEscrowPda(1))).value.amount).eq("40");
+      expect((await program.account.dealDetails.fetch(dealPda(basketDealId))).state).to.deep.equal({ takerDeposited: {} });
+
+      await program.methods
+        .withdraw(basketDealId)
+        .accounts({
+          maker: maker.publicKey,
+          signer: maker.publicKey,
+          userTokenAcc: ata(mintB.publicKey, maker.publicKey),
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          mintExchange: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          fillDetails: null,
+          basketDetails: basketPda(),
+        })
+        .remainingAccounts(legAccounts(maker.publicKey))
+        .signers([maker])
+        .rpc();
+      expect((await provider.connection.getTokenAccountBalance(ata(mintD.publicKey, maker.publicKey))).value.amount).eq("40");
+
+      await program.methods
+        .withdraw(basketDealId)
+        .accounts({
+          maker: maker.publicKey,
+          signer: taker.publicKey,
+          userTokenAcc: ata(mintA.publicKey, taker.publicKey),
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          mintExchange: mintA.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          fillDetails: anchor.web3.PublicKey.findProgramAddressSync(
+            [Buffer.from("fill"), dealPda(basketDealId).toBuffer(), taker.publicKey.toBuffer()],
+            program.programId
+          )[0],
+          basketDetails: basketPda(),
+        })
+        .remainingAccounts(legAccounts(taker.publicKey))
+        .signers([taker])
+        .rpc();
+      expect((await provider.connection.getTokenAccountBalance(ata(mintC.publicKey, taker.publicKey))).value.amount).eq("50");
+
+      expect(await program.account.dealDetails.fetchNullable(dealPda(basketDealId))).to.be.null;
+      expect(await program.account.basketDetails.fetchNullable(basketPda())).to.be.null;
+      expect(await provider.connection.getAccountInfo(legEscrowPda(0))).to.be.null;
+    });
+  });
+
+  describe("Arbiter disputes", () => {
+    const disputeDealId = new anchor.BN(19);
+    const arbiter = anchor.web3.Keypair.generate();
+
+    const balance = async (account: anchor.web3.PublicKey) =>
+      Number((await provider.connection.getTokenAccountBalance(account)).value.amount);
+
+    const createDeal = (taker: anchor.web3.PublicKey | null) =>
+      program.methods
+        .create(disputeDealId, new anchor.BN(100), new anchor.BN(10), inAnHour(), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker,
+          arbiter: arbiter.publicKey,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+
+    const resolve = (signer: anchor.web3.Keypair, splitBps: number) =>
+      program.methods
+        .resolveDispute(disputeDealId, splitBps)
+        .accounts({
+          arbiter: signer.publicKey,
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([signer])
+        .rpc();
+
+    before(async () => {
+      const airDropTx = await provider.connection.requestAirdrop(arbiter.publicKey, anchor.web3.LAMPORTS_PER_SOL);
+      await provider.connection.confirmTransaction(airDropTx, "confirmed");
+      await fundParties(100, 10);
+    });
+
+    it("Rejects an arbiter on an open offer", async () => {
+      await expectError(createDeal(null), "InvalidArbiter");
+    });
+
+    it("Taker raises a dispute and withdraw is frozen", async () => {
+      await createDeal(taker.publicKey);
+      await program.methods
+        .deposit(disputeDealId, new anchor.BN(10), new anchor.BN(100), new anchor.BN(10), [])
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mint: mintB.publicKey,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([taker])
+        .rpc();
+
+      await program.methods
+        .raiseDispute(disputeDealId)
+        .accounts({ signer: taker.publicKey, maker: maker.publicKey })
+        .signers([taker])
+        .rpc();
+      expect((await program.account.dealDetails.fetch(dealPda(disputeDealId))).disputeOpen).to.be.true;
+
+      await expectError(
+        program.methods
+          .withdraw(disputeDealId)
+          .accounts({
+            maker: maker.publicKey,
+            signer: maker.publicKey,
+            userTokenAcc: ata(mintB.publicKey, maker.publicKey),
+            mintA: mintA.publicKey,
+            mintB: mintB.publicKey,
+            mintExchange: mintB.publicKey,
+            tokenProgramA: TOKEN_2022_PROGRAM_ID,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+            fillDetails: null,
+          })
+          .signers([maker])
+          .rpc(),
+        "DisputeOpen"
+      );
+    });
+
+    it("Only the arbiter can resolve the dispute", async () => {
+      await expectError(resolve(taker, 10_000), "InvalidUser");
+    });
+
+    it("Arbiter splits both escrows and closes the deal", async () => {
+      const makerABefore = await balance(ata(mintA.publicKey, maker.publicKey));
+      const takerABefore = await balance(ata(mintA.publicKey, taker.publicKey));
+      const makerBBefore = await balance(ata(mintB.publicKey, maker.publicKey));
+      const takerBBefore = await balance(ata(mintB.publicKey, taker.publicKey));
+
+      // a quarter of the trade goes through, the rest is refunded to each side
+      await resolve(arbiter, 2_500);
+
+      expect((await balance(ata(mintA.publicKey, taker.publicKey))) - takerABefore).eq(25);
+      expect((await balance(ata(mintA.publicKey, maker.publicKey))) - makerABefore).eq(75);
+      expect((await balance(ata(mintB.publicKey, maker.publicKey))) - makerBBefore).eq(2);
+      expect((await balance(ata(mintB.publicKey, taker.publicKey))) - takerBBefore).eq(8);
+      expect(await program.account.dealDetails.fetchNullable(dealPda(disputeDealId))).to.be.null;
+    });
+  });
+
+  describe("Milestone deals", () => {
+    const milestoneDealId = new anchor.BN(20);
+
+    const userADetailsPda = () =>
+      anchor.web3.PublicKey.findProgramAddressSync([Buffer.from("user_a_details"), dealPda(milestoneDealId).toBuffer()], program.programId)[0];
+    const takerMintAata = () => ata(mintA.publicKey, taker.publicKey);
+    const takerMintABalance = async () =>
+      Number((await provider.connection.getTokenAccountBalance(takerMintAata())).value.amount);
+
+    const setMilestones = (amounts: number[]) =>
+      program.methods
+        .setMilestones(milestoneDealId, amounts.map((amount) => new anchor.BN(amount)))
+        .accounts({ maker: maker.publicKey })
+        .signers([maker])
+        .rpc();
+
+    const approve = (index: number, signer = maker) =>
+      program.methods
+        .approveMilestone(milestoneDealId, index)
+        .accounts({ signer: signer.publicKey, maker: maker.publicKey })
+        .signers([signer])
+        .rpc();
+
+    const takerWithdraw = () =>
+      program.methods
+        .withdraw(milestoneDealId)
+        .accounts({
+          maker: maker.publicKey,
+          signer: taker.publicKey,
+          userTokenAcc: takerMintAata(),
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          mintExchange: mintA.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          fillDetails: anchor.web3.PublicKey.findProgramAddressSync(
+            [Buffer.from("fill"), dealPda(milestoneDealId).toBuffer(), taker.publicKey.toBuffer()],
+            program.programId
+          )[0],
+        })
+        .signers([taker])
+        .rpc();
+
+    before(async () => {
+      await fundParties(100, 10);
+
+      await program.methods
+        .create(milestoneDealId, new anchor.BN(100), new anchor.BN(10), inAnHour(), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+    });
+
+    it("Rejects milestones that do not add up to the maker amount", async () => {
+      await expectError(setMilestones([30, 60]), "InvalidMilestones");
+    });
+
+    it("Rejects basket legs once milestones are set", async () => {
+      await setMilestones([30, 70]);
+      await expectError(
+        program.methods
+          .addLeg(milestoneDealId, { taker: {} }, new anchor.BN(40))
+          .accountsPartial({
+            maker: maker.publicKey,
+            dealDetails: dealPda(milestoneDealId),
+            mint: mintB.publicKey,
+            legEscrowTokenAcc: anchor.web3.PublicKey.findProgramAddressSync(
+              [Buffer.from("leg"), dealPda(milestoneDealId).toBuffer(), Buffer.from([0])],
+              program.programId
+            )[0],
+            makerTokenAcc: null,
+            tokenProgram: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([maker])
+          .rpc(),
+        "MilestonesWithBasket"
+      );
+    });
+
+    it("Keeps mint_a locked until a milestone is approved", async () => {
+      await program.methods
+        .deposit(milestoneDealId, new anchor.BN(10), new anchor.BN(100), new anchor.BN(10), [])
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mint: mintB.publicKey,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([taker])
+        .rpc();
+
+      await expectError(takerWithdraw(), "IncompleteDeal");
+      await expectError(approve(1), "MilestoneOutOfOrder");
+    });
+
+    it("The taker can't approve the tranches paid out to itself", async () => {
+      await expectError(approve(0, taker), "InvalidUser");
+    });
+
+    it("Releases each tranche as it is approved", async () => {
+      const before = await takerMintABalance();
+
+      await approve(0);
+      await takerWithdraw();
+      expect((await takerMintABalance()) - before).eq(30);
+
+      const userADetails = await program.account.userEscrowDetails.fetch(userADetailsPda());
+      expect(userADetails.releasedAmt.toNumber()).eq(30);
+      expect(userADetails.milestonesReleased).eq(1);
+
+      await approve(1);
+      await takerWithdraw();
+      expect((await takerMintABalance()) - before).eq(100);
+      expect((await program.account.dealDetails.fetch(dealPda(milestoneDealId))).state).to.deep.equal({ takerWithdrew: {} });
+    });
+  });
+
+  describe("Price conditional deals", () => {
+    const priceDealId = new anchor.BN(21);
+    const oracleProgram = anchor.workspace.mockOracle as Program<MockOracle>;
+    const [priceFeed] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("price"), tokenMaker.publicKey.toBuffer()],
+      oracleProgram.programId
+    );
+
+    const setPrice = (price: number) =>
+      oracleProgram.methods.setPrice(new anchor.BN(price)).accounts({ authority: tokenMaker.publicKey }).rpc();
+    const setPriceAt = (price: number, publishTime: number) =>
+      oracleProgram.methods
+        .setPriceAt(new anchor.BN(price), new anchor.BN(publishTime))
+        .accounts({ authority: tokenMaker.publicKey })
+        .rpc();
+
+    const setCondition = (min: number, max: number) =>
+      program.methods
+        .setPriceCondition(priceDealId, new anchor.BN(min), new anchor.BN(max))
+        .accounts({ maker: maker.publicKey, oracle: priceFeed })
+        .signers([maker])
+        .rpc();
+
+    const deposit = (priceOracle: anchor.web3.PublicKey | null) =>
+      program.methods
+        .deposit(priceDealId, new anchor.BN(10), new anchor.BN(100), new anchor.BN(10), [])
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mint: mintB.publicKey,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          priceOracle,
+        })
+        .signers([taker])
+        .rpc();
+
+    before(async () => {
+      await fundParties(100, 10);
+      await setPrice(100);
+
+      await program.methods
+        .create(priceDealId, new anchor.BN(100), new anchor.BN(10), inAnHour(), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+    });
+
+    it("Rejects a range with the minimum above the maximum", async () => {
+      await expectError(setCondition(110, 90), "InvalidPriceCondition");
+    });
+
+    it("Rejects fills without the oracle or outside the range", async () => {
+      await setCondition(90, 110);
+      await expectError(deposit(null), "InvalidOracle");
+
+      await setPrice(150);
+      await expectError(deposit(priceFeed), "PriceOutOfRange");
+    });
+
+    it("Rejects a price published ahead of the cluster clock", async () => {
+      await setPriceAt(100, (await clusterTime()) + 600);
+      await expectError(deposit(priceFeed), "FuturePrice");
+    });
+
+    it("Fills once the price is back in range", async () => {
+      await setPrice(100);
+      await deposit(priceFeed);
+
+      const deal = await program.account.dealDetails.fetch(
+        dealPda(priceDealId)
+      );
+      expect(deal.state).to.deep.equal({ takerDeposited: {} });
+    });
+  });
+
+  describe("Slippage protection", () => {
+    const slippageDealId = new anchor.BN(22);
+
+    const deposit = (expectedMakerAmt: number, maxTakerAmt: number) =>
+      program.methods
+        .deposit(slippageDealId, new anchor.BN(10), new anchor.BN(expectedMakerAmt), new anchor.BN(maxTakerAmt), [])
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mint: mintB.publicKey,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([taker])
+        .rpc();
+
+    before(async () => {
+      await fundParties(100, 0);
+      await program.methods
+        .create(slippageDealId, new anchor.BN(100), new anchor.BN(10), inAnHour(), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+    });
+
+    for (const [title, expectedMakerAmt, maxTakerAmt] of [
+      ["less mint_a than the taker expected", 120, 10],
+      ["a higher price than the taker accepts", 100, 9],
+    ] as const) {
+      it(`Rejects a fill for ${title}`, async () => {
+        await expectError(
+          deposit(expectedMakerAmt, maxTakerAmt),
+          "TermsChanged"
+        );
+      });
+    }
+  });
+
+  describe("Counter offers", () => {
+    const counterDealId = new anchor.BN(23);
+
+    const proposalPda = (proposer: anchor.web3.PublicKey) =>
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("proposal"), dealPda(counterDealId).toBuffer(), proposer.toBuffer()],
+        program.programId
+      )[0];
+    const escrowA = () =>
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("token_a"), dealPda(counterDealId).toBuffer()],
+        program.programId
+      )[0];
+    const balance = async (account: anchor.web3.PublicKey) =>
+      Number((await provider.connection.getTokenAccountBalance(account)).value.amount);
+
+    const counter = (signer: anchor.web3.Keypair, makerAmt: number, takerAmt: number) =>
+      program.methods
+        .counterOffer(counterDealId, new anchor.BN(makerAmt), new anchor.BN(takerAmt), [])
+        .accounts({ taker: signer.publicKey, maker: maker.publicKey })
+        .signers([signer])
+        .rpc();
+
+    const accept = (acceptMintA: anchor.web3.PublicKey | null = mintA.publicKey) =>
+      program.methods
+        .acceptCounter(counterDealId)
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: acceptMintA,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+
+    before(async () => {
+      await fundParties(150, 12);
+      await program.methods
+        .create(counterDealId, new anchor.BN(100), new anchor.BN(10), inAnHour(), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+    });
+
+    it("Only the deal's taker can counter", async () => {
+      await expectError(counter(secondMaker, 100, 8), "InvalidUser");
+    });
+
+    it("Maker rejects a counter and the taker gets the rent back", async () => {
+      await counter(taker, 100, 5);
+      await program.methods
+        .rejectCounter(counterDealId)
+        .accounts({ signer: maker.publicKey, maker: maker.publicKey, taker: taker.publicKey })
+        .signers([maker])
+        .rpc();
+      expect(await program.account.proposal.fetchNullable(proposalPda(taker.publicKey))).to.be.null;
+    });
+
+    it("Accepting a larger maker amount tops up the escrow", async () => {
+      await counter(taker, 150, 12);
+      // a token top up can't be paid into the controller in lamports instead
+      await expectError(accept(null), "InvalidMint");
+      const makerBefore = await balance(ataMakerMintA!);
+
+      await accept();
+
+      expect(makerBefore - (await balance(ataMakerMintA!))).eq(50);
+      expect(await balance(escrowA())).eq(150);
+      const userB = await program.account.userEscrowDetails.fetch(
+        anchor.web3.PublicKey.findProgramAddressSync([Buffer.from("user_b_details"), dealPda(counterDealId).toBuffer()], program.programId)[0]
+      );
+      expect(userB.mintAmt.toNumber()).eq(12);
+      expect(await program.account.proposal.fetchNullable(proposalPda(taker.publicKey))).to.be.null;
+    });
+
+    it("Accepting a smaller maker amount refunds the difference", async () => {
+      await counter(taker, 80, 12);
+      const makerBefore = await balance(ataMakerMintA!);
+
+      await accept();
+
+      expect((await balance(ataMakerMintA!)) - makerBefore).eq(70);
+      expect(await balance(escrowA())).eq(80);
+    });
+
+    it("Taker fills on the new terms", async () => {
+      // left open while the deal settles
+      await counter(taker, 80, 11);
+      await program.methods
+        .take(counterDealId, new anchor.BN(80), new anchor.BN(12), [])
+        .accounts({
+          taker: taker.publicKey,
+          maker: maker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([taker])
+        .rpc();
+      expect(await program.account.dealDetails.fetchNullable(dealPda(counterDealId))).to.be.null;
+    });
+
+    it("Taker reclaims the rent of a proposal left open on a closed deal", async () => {
+      const rent = await provider.connection.getBalance(proposalPda(taker.publicKey));
+      const takerBefore = await provider.connection.getBalance(taker.publicKey);
+
+      await program.methods
+        .rejectCounter(counterDealId)
+        .accounts({ signer: taker.publicKey, maker: maker.publicKey, taker: taker.publicKey })
+        .signers([taker])
+        .rpc();
+
+      expect(await program.account.proposal.fetchNullable(proposalPda(taker.publicKey))).to.be.null;
+      // the taker also paid the transaction fee
+      expect((await provider.connection.getBalance(taker.publicKey)) - takerBefore).gt(rent - 10_000);
+    });
+
+    it("Accepting a counter on an open offer binds it to the proposer", async () => {
+      const openCounterDealId = new anchor.BN(36);
+      await fundParties(100, 0);
+      await program.methods
+        .create(openCounterDealId, new anchor.BN(100), new anchor.BN(10), inAnHour(), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker: null,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+
+      await program.methods
+        .counterOffer(openCounterDealId, new anchor.BN(100), new anchor.BN(8), [])
+        .accounts({ taker: taker.publicKey, maker: maker.publicKey })
+        .signers([taker])
+        .rpc();
+      await program.methods
+        .acceptCounter(openCounterDealId)
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mintA: mintA.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+
+      const openCounterDealPda = dealPda(openCounterDealId);
+      expect((await program.account.dealDetails.fetch(openCounterDealPda)).taker.equals(taker.publicKey)).to.be.true;
+
+      // someone else can't fill at the terms the proposer negotiated
+      await expectError(
+        program.methods
+          .deposit(openCounterDealId, new anchor.BN(8), new anchor.BN(100), new anchor.BN(8), [])
+          .accounts({
+            maker: maker.publicKey,
+            taker: secondMaker.publicKey,
+            mint: mintB.publicKey,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([secondMaker])
+          .rpc(),
+        "InvalidUser"
+      );
+    });
+  });
+
+  describe("Taker allowlists", () => {
+    const allowlistDealId = new anchor.BN(24);
+
+    const deposit = (amount: number, proof: Buffer[]) =>
+      program.methods
+        .deposit(allowlistDealId, new anchor.BN(amount), new anchor.BN(100), new anchor.BN(10), proof.map(node => Array.from(node)))
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mint: mintB.publicKey,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([taker])
+        .rpc();
+
+    const setAllowlist = (allowlist: Parameters<typeof program.methods.setTakerAllowlist>[1]) =>
+      program.methods
+        .setTakerAllowlist(allowlistDealId, allowlist)
+        .accounts({ maker: maker.publicKey })
+        .signers([maker])
+        .rpc();
+
+    // leaves are keccak(taker), pairs are hashed smaller first like the program does
+    const leaf = (wallet: anchor.web3.PublicKey) => Buffer.from(keccak_256(wallet.toBuffer()));
+    const hashPair = (a: Buffer, b: Buffer) =>
+      Buffer.from(keccak_256(Buffer.compare(a, b) <= 0 ? Buffer.concat([a, b]) : Buffer.concat([b, a])));
+
+    before(async () => {
+      await fundParties(100, 10);
+      await program.methods
+        .create(allowlistDealId, new anchor.BN(100), new anchor.BN(10), inAnHour(), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker: null,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+    });
+
+    it("Rejects an empty allowlist", async () => {
+      await expectError(setAllowlist({ takers: { takers: [] } }), "InvalidAllowlist");
+    });
+
+    it("Only listed takers can fill", async () => {
+      await setAllowlist({ takers: { takers: [secondMaker.publicKey] } });
+      await expectError(deposit(5, []), "TakerNotAllowed");
+      await expectError(
+        program.methods
+          .counterOffer(allowlistDealId, new anchor.BN(100), new anchor.BN(5), [])
+          .accounts({ taker: taker.publicKey, maker: maker.publicKey })
+          .signers([taker])
+          .rpc(),
+        "TakerNotAllowed"
+      );
+    });
+
+    it("Takers under a Merkle root fill with a proof", async () => {
+      const root = hashPair(leaf(taker.publicKey), leaf(secondMaker.publicKey));
+      await setAllowlist({ merkleRoot: { root: Array.from(root) } });
+
+      await expectError(deposit(5, []), "TakerNotAllowed");
+      await deposit(5, [leaf(secondMaker.publicKey)]);
+
+      const deal = await program.account.dealDetails.fetch(
+        dealPda(allowlistDealId)
+      );
+      expect(deal.takerAmtRemaining.toNumber()).eq(5);
+    });
+  });
+
+  describe("Signed offers", () => {
+    const [offerDelegate] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("delegate"), maker.publicKey.toBuffer()],
+      program.programId
+    );
+    const balance = async (account: anchor.web3.PublicKey) =>
+      Number((await provider.connection.getTokenAccountBalance(account)).value.amount);
+
+    const newOffer = (nonce: number) => ({
       maker: maker.publicKey,
-      taker: taker.publicKey,
-      tokenProgram: TOKEN_2022_PROGRAM_ID
-    }).signers([maker]).rpc();
+      mintA: mintA.publicKey,
+      mintB: mintB.publicKey,
+      makerAmt: new anchor.BN(20),
+      takerAmt: new anchor.BN(2),
+      expiresAt: inAnHour(),
+      nonce: new anchor.BN(nonce),
+    });
+
+    // what the maker signs, the domain and program id followed by the borsh layout of SignedOffer
+    const offerMessage = (offer: ReturnType<typeof newOffer>, programId = program.programId) =>
+      Buffer.concat([
+        Buffer.from("escrow-offer"),
+        programId.toBuffer(),
+        offer.maker.toBuffer(),
+        offer.mintA.toBuffer(),
+        offer.mintB.toBuffer(),
+        offer.makerAmt.toArrayLike(Buffer, "le", 8),
+        offer.takerAmt.toArrayLike(Buffer, "le", 8),
+        offer.expiresAt.toArrayLike(Buffer, "le", 8),
+        offer.nonce.toArrayLike(Buffer, "le", 8),
+      ]);
+
+    const takeOffer = (offer: ReturnType<typeof newOffer>, signed = offer, programId = program.programId) =>
+      program.methods
+        .takeSignedOffer(offer)
+        .accounts({
+          taker: taker.publicKey,
+          maker: maker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .preInstructions([
+          anchor.web3.Ed25519Program.createInstructionWithPrivateKey({
+            privateKey: maker.secretKey,
+            message: offerMessage(signed, programId),
+          }),
+        ])
+        .signers([taker])
+        .rpc();
+
+    before(async () => {
+      await fundParties(40, 4);
+      // the maker only approves the program's delegate, nothing is escrowed
+      await approveChecked(provider.connection, tokenMaker.payer, mintA.publicKey, ataMakerMintA, offerDelegate, maker, 40, 3, [], undefined, TOKEN_2022_PROGRAM_ID);
+    });
+
+    it("Rejects an offer whose terms differ from what the maker signed", async () => {
+      const offer = newOffer(1);
+      await expectError(
+        takeOffer({ ...offer, makerAmt: new anchor.BN(40) }, offer),
+        "InvalidSignature"
+      );
+    });
+
+    it("Rejects an offer signed for another program", async () => {
+      const offer = newOffer(1);
+      await expectError(takeOffer(offer, offer, anchor.web3.Keypair.generate().publicKey), "InvalidSignature");
+    });
+
+    it("Taker settles a signed offer against the maker's delegate", async () => {
+      const makerABefore = await balance(ataMakerMintA!);
+      const takerBBefore = await balance(ataTakerMintB!);
+
+      await takeOffer(newOffer(1));
+
+      expect(makerABefore - (await balance(ataMakerMintA!))).eq(20);
+      expect(takerBBefore - (await balance(ataTakerMintB!))).eq(2);
+      expect(await balance(ata(mintA.publicKey, taker.publicKey))).gte(20);
+    });
+
+    it("The same signed offer can't be taken twice", async () => {
+      const err = await rejection(takeOffer(newOffer(1)));
+      expect((err as anchor.web3.SendTransactionError).logs?.join("\n")).to.include("already in use");
+    });
+
+    it("Maker cancels an offer by burning its nonce", async () => {
+      const offer = newOffer(2);
+      await program.methods
+        .cancelSignedOffer(offer.nonce, offer.expiresAt)
+        .accounts({ maker: maker.publicKey })
+        .signers([maker])
+        .rpc();
+      const err = await rejection(takeOffer(offer));
+      expect((err as anchor.web3.SendTransactionError).logs?.join("\n")).to.include("already in use");
+    });
+
+    it("Rejects closing a nonce before its offer expired", async () => {
+      await expectError(
+        program.methods
+          .closeOfferNonce(new anchor.BN(1))
+          .accounts({ maker: maker.publicKey, payer: taker.publicKey })
+          .rpc(),
+        "DealNotExpired"
+      );
+    });
+
+    it("The taker gets the nonce rent back once the offer expired", async () => {
+      const offer = { ...newOffer(3), expiresAt: new anchor.BN((await clusterTime()) + 2) };
+      await takeOffer(offer);
+      const [noncePda] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("nonce"), maker.publicKey.toBuffer(), offer.nonce.toArrayLike(Buffer, "le", 8)],
+        program.programId
+      );
+      const rent = await provider.connection.getBalance(noncePda);
+      const takerBefore = await provider.connection.getBalance(taker.publicKey);
+
+      await waitUntilPast(offer.expiresAt.toNumber());
+      // cranked by the provider wallet, the rent still goes to the taker who paid it
+      await program.methods
+        .closeOfferNonce(offer.nonce)
+        .accounts({ maker: maker.publicKey, payer: taker.publicKey })
+        .rpc();
 
-    console.log("All accounds closed succesfully : ", tx);
+      expect(await provider.connection.getAccountInfo(noncePda)).to.be.null;
+      expect((await provider.connection.getBalance(taker.publicKey)) - takerBefore).eq(rent);
+      // the expired offer can't be taken again now that its nonce is gone
+      await expectError(takeOffer(offer), "DealExpired");
+    });
+  });
+
+  describe("Order index", () => {
+    const [firstDealId, secondDealId] = [new anchor.BN(25), new anchor.BN(26)];
+    const orderIndex = orderIndexPda(program.programId, mintA.publicKey, mintB.publicKey);
+
+    const createDeal = (id: anchor.BN, dealTaker: anchor.web3.PublicKey | null, index = orderIndex) =>
+      program.methods
+        .create(id, new anchor.BN(100), new anchor.BN(10), inAnHour(), false)
+        .accounts({
+          maker: maker.publicKey,
+          taker: dealTaker,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          orderIndex: index,
+        })
+        .signers([maker])
+        .rpc();
+
+    const cancel = (id: anchor.BN, index: anchor.web3.PublicKey | null) =>
+      program.methods
+        .cancel(id)
+        .accounts({
+          maker: maker.publicKey,
+          mintA: mintA.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          orderIndex: index,
+        })
+        .signers([maker])
+        .rpc();
+
+    before(async () => {
+      await fundParties(200, 10);
+      await program.methods
+        .initOrderIndex(mintA.publicKey, mintB.publicKey, 0)
+        .accounts({ payer: maker.publicKey })
+        .signers([maker])
+        .rpc();
+    });
+
+    it("Only open offers are listed", async () => {
+      await expectError(createDeal(new anchor.BN(27), taker.publicKey), "IndexRequiresOpenOffer");
+    });
+
+    it("Create lists open offers under their mint pair", async () => {
+      await createDeal(firstDealId, null);
+      await createDeal(secondDealId, null);
+
+      const openDeals = await fetchOpenDeals(program, mintA.publicKey, mintB.publicKey);
+      expect(openDeals.map(({ address }) => address.toBase58())).to.have.members([
+        dealPda(firstDealId).toBase58(),
+        dealPda(secondDealId).toBase58(),
+      ]);
+    });
+
+    it("A listed deal can't end without taking it off the index", async () => {
+      await expectError(cancel(firstDealId, null), "MissingOrderIndex");
+    });
+
+    it("Cancel and take remove deals from the index", async () => {
+      await cancel(firstDealId, orderIndex);
+      await program.methods
+        .take(secondDealId, new anchor.BN(100), new anchor.BN(10), [])
+        .accounts({
+          taker: taker.publicKey,
+          maker: maker.publicKey,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          orderIndex,
+        })
+        .signers([taker])
+        .rpc();
+
+      expect(await fetchOpenDeals(program, mintA.publicKey, mintB.publicKey)).to.be.empty;
+      expect((await program.account.orderIndex.fetch(orderIndex)).deals).to.be.empty;
+    });
+
+    it("Deals can be listed on any page of the pair", async () => {
+      const pagedDealId = new anchor.BN(37);
+      const secondPage = orderIndexPda(program.programId, mintA.publicKey, mintB.publicKey, 1);
+      await fundParties(100, 0);
+      // anyone can open another page, a full one doesn't keep makers from listing
+      await program.methods
+        .initOrderIndex(mintA.publicKey, mintB.publicKey, 1)
+        .accounts({ payer: secondMaker.publicKey })
+        .signers([secondMaker])
+        .rpc();
+      await createDeal(pagedDealId, null, secondPage);
+
+      expect((await fetchOpenDeals(program, mintA.publicKey, mintB.publicKey)).map(({ address }) => address.toBase58())).to.deep.equal([
+        dealPda(pagedDealId).toBase58(),
+      ]);
+      expect((await program.account.dealDetails.fetch(dealPda(pagedDealId))).indexPage).eq(1);
+
+      await expectError(cancel(pagedDealId, orderIndex), "InvalidOrderIndex");
+      await cancel(pagedDealId, secondPage);
+      expect((await program.account.orderIndex.fetch(secondPage)).deals).to.be.empty;
+    });
 
-    const afterMakerBalance = await provider.connection.getBalance(maker.publicKey);
-    console.log("Makers balance after : ", afterMakerBalance);
-    expect(beforeMakerBalance).lessThan(afterMakerBalance);
+    it("A deal filled through deposit leaves the index and closes on the last withdraw", async () => {
+      const filledDealId = new anchor.BN(28);
+      await fundParties(100, 10);
+      await createDeal(filledDealId, null);
 
-  })
+      // the last fill is what takes the deal off, the index has to come along with it
+      await program.methods
+        .deposit(filledDealId, new anchor.BN(10), new anchor.BN(100), new anchor.BN(10), [])
+        .accounts({
+          maker: maker.publicKey,
+          taker: taker.publicKey,
+          mint: mintB.publicKey,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+          orderIndex,
+        })
+        .signers([taker])
+        .rpc();
+      expect((await program.account.orderIndex.fetch(orderIndex)).deals).to.be.empty;
+
+      const withdraw = (signer: anchor.web3.Keypair, mintExchange: anchor.web3.PublicKey, fillDetails: anchor.web3.PublicKey | null) =>
+        program.methods
+          .withdraw(filledDealId)
+          .accounts({
+            maker: maker.publicKey,
+            signer: signer.publicKey,
+            userTokenAcc: ata(mintExchange, signer.publicKey),
+            mintA: mintA.publicKey,
+            mintB: mintB.publicKey,
+            mintExchange,
+            tokenProgramA: TOKEN_2022_PROGRAM_ID,
+            tokenProgramB: TOKEN_2022_PROGRAM_ID,
+            fillDetails,
+            orderIndex,
+          })
+          .signers([signer])
+          .rpc();
+
+      const [fillPda] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("fill"), dealPda(filledDealId).toBuffer(), taker.publicKey.toBuffer()],
+        program.programId
+      );
+      await withdraw(maker, mintB.publicKey, null);
+      await withdraw(taker, mintA.publicKey, fillPda);
+
+      expect(await program.account.dealDetails.fetchNullable(dealPda(filledDealId))).to.be.null;
+      expect((await program.account.orderIndex.fetch(orderIndex)).deals).to.be.empty;
+    });
+  });
+
+  describe("First taker binding", () => {
+    const [boundDealId, fixedTakerDealId] = [new anchor.BN(34), new anchor.BN(35)];
+
+    const createDeal = (id: anchor.BN, dealTaker: anchor.web3.PublicKey | null, bindFirstTaker: boolean) =>
+      program.methods
+        .create(id, new anchor.BN(100), new anchor.BN(10), inAnHour(), bindFirstTaker)
+        .accounts({
+          maker: maker.publicKey,
+          taker: dealTaker,
+          mintA: mintA.publicKey,
+          mintB: mintB.publicKey,
+          tokenProgramA: TOKEN_2022_PROGRAM_ID,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([maker])
+        .rpc();
+
+    const deposit = (user: anchor.web3.Keypair, amount: number) =>
+      program.methods
+        .deposit(boundDealId, new anchor.BN(amount), new anchor.BN(100), new anchor.BN(10), [])
+        .accounts({
+          maker: maker.publicKey,
+          taker: user.publicKey,
+          mint: mintB.publicKey,
+          tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+
+    before(async () => {
+      await fundParties(200, 10);
+    });
+
+    it("Only open offers can be bound", async () => {
+      await expectError(createDeal(fixedTakerDealId, taker.publicKey, true), "BindingRequiresOpenOffer");
+    });
+
+    it("The first taker to fill a bound offer is the only one who can fill the rest", async () => {
+      // bound from creation, there is no window in which someone else could fill it
+      await createDeal(boundDealId, null, true);
+      await deposit(taker, 4);
+
+      const boundDealPda = dealPda(boundDealId);
+      const deal = await program.account.dealDetails.fetch(boundDealPda);
+      expect(deal.taker?.toBase58()).eq(taker.publicKey.toBase58());
+
+      await expectError(deposit(secondMaker, 2), "InvalidUser");
+      await deposit(taker, 6);
+    });
+  });
 });